@@ -0,0 +1,2 @@
+-- set when the instance rejects our token, e.g. because the user revoked the app
+alter table accounts add column needs_reauth boolean not null default false;
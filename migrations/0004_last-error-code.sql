@@ -0,0 +1,3 @@
+-- machine-readable counterpart to last_error, see SyncError::code
+alter table accounts add column last_error_code text;
+update accounts set last_error_code = 'internal' where last_error is not null;
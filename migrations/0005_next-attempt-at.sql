@@ -0,0 +1,3 @@
+-- when the scheduler should sync this account next. null means as soon as possible.
+alter table accounts add column next_attempt_at datetime;
+update accounts set next_attempt_at = datetime(last_success_at, '+1 days') where last_success_at is not null;
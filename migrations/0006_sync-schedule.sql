@@ -0,0 +1,4 @@
+-- see schedule::SyncFrequency for the format
+alter table accounts add column sync_frequency text not null default 'daily';
+-- UTC hour of day, 0-23
+alter table accounts add column preferred_hour integer;
@@ -0,0 +1,28 @@
+create table if not exists sync_history
+(
+    id integer primary key autoincrement,
+    host text not null,
+    username text not null,
+    started_at datetime not null,
+    finished_at datetime not null,
+    -- null on success, see SyncError::code
+    error_code text,
+    error text,
+    list_count integer,
+    foreign key (host, username) references accounts (host, username) on delete cascade
+);
+
+create index if not exists sync_history_account on sync_history (host, username, started_at);
+
+-- personal access tokens for the JSON API. only a hash of the token is stored.
+create table if not exists api_tokens
+(
+    id integer primary key autoincrement,
+    host text not null,
+    username text not null,
+    name text not null,
+    token_hash text not null unique,
+    created_at datetime not null,
+    last_used_at datetime,
+    foreign key (host, username) references accounts (host, username) on delete cascade
+);
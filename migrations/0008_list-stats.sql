@@ -0,0 +1,14 @@
+-- outcome of the last successful sync of each managed list
+create table if not exists list_stats
+(
+    host text not null,
+    username text not null,
+    list_id text not null,
+    title text not null,
+    member_count integer not null,
+    added integer not null,
+    removed integer not null,
+    synced_at datetime not null,
+    primary key (host, username, list_id),
+    foreign key (host, username) references accounts (host, username) on delete cascade
+);
@@ -0,0 +1,10 @@
+-- OAuth apps registered via /api/v1/apps, reused for all logins on the same instance
+create table if not exists oauth_apps
+(
+    host text primary key not null,
+    client_id text not null,
+    client_secret text not null,
+    redirect_uri text not null,
+    scopes text not null,
+    created_at datetime not null
+);
@@ -0,0 +1,15 @@
+-- membership of a list before a sync changed it, for reverting the sync
+create table if not exists list_snapshots
+(
+    id integer primary key autoincrement,
+    host text not null,
+    username text not null,
+    list_id text not null,
+    list_title text not null,
+    sync_started_at datetime not null,
+    -- JSON array of {id, acct}
+    members text not null,
+    foreign key (host, username) references accounts (host, username) on delete cascade
+);
+
+create index if not exists list_snapshots_account on list_snapshots (host, username, sync_started_at);
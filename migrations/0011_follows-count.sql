@@ -0,0 +1,2 @@
+-- number of follows at the last successful sync, see SafetyLimits::check_follows
+alter table accounts add column follows_count integer;
@@ -0,0 +1,13 @@
+-- members of each list as of the last sync, for incremental syncs
+create table if not exists list_records
+(
+    host text not null,
+    username text not null,
+    list_id text not null,
+    list_title text not null,
+    verified_at datetime not null,
+    -- JSON array of {id, acct}
+    members text not null,
+    primary key (host, username, list_id),
+    foreign key (host, username) references accounts (host, username) on delete cascade
+);
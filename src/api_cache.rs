@@ -1,16 +1,18 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use anyhow::{Context, Error};
 use itertools::Itertools;
 
-use crate::api_client::ApiClient;
+use crate::api_client::{ApiClient, SyncContext};
 use crate::api_helpers;
 use crate::api_models::{Account, CredentialAccount, Relationship};
+use crate::error::SyncError;
+use crate::progress::SyncProgress;
 
 const RELATIONSHIP_FETCH_CHUNK_SIZE: usize = 40;
 
 #[derive(Default)]
 pub struct ApiCache {
+    credentials: Option<CredentialAccount>,
     follows: Option<Vec<Account>>,
     relationships: BTreeMap<String, Relationship>,
 }
@@ -20,7 +22,7 @@ impl ApiCache {
         &mut self,
         client: &ApiClient,
         mut account_ids: BTreeSet<String>,
-    ) -> Result<Vec<Relationship>, Error> {
+    ) -> Result<Vec<Relationship>, SyncError> {
         let mut result = Vec::new();
 
         account_ids.retain(|id| {
@@ -32,12 +34,19 @@ impl ApiCache {
             }
         });
 
+        let total = account_ids.len();
+        let mut done = 0;
+
         for account_chunk in account_ids
             .into_iter()
             .collect_vec()
             .chunks(RELATIONSHIP_FETCH_CHUNK_SIZE)
         {
             let account_ids = account_chunk.to_vec();
+            client
+                .progress
+                .report(SyncProgress::FetchingRelationships { done, total });
+            done += account_ids.len();
 
             tracing::debug!("fetching relationships: {:?}", account_ids);
 
@@ -51,12 +60,12 @@ impl ApiCache {
                     }),
                 )
                 .await
-                .context("failed to get relationships")?
+                .sync_context("failed to get relationships")?
                 .error_for_status()
-                .context("failed to get relationships")?
+                .sync_context("failed to get relationships")?
                 .json()
                 .await
-                .context("failed to parse relationships")?;
+                .sync_context("failed to parse relationships")?;
 
             for relationship in chunk_result {
                 self.relationships
@@ -68,42 +77,63 @@ impl ApiCache {
         Ok(result)
     }
 
-    pub async fn get_follows(&mut self, client: &ApiClient) -> Result<&[Account], Error> {
-        if let Some(ref follows) = self.follows {
-            return Ok(follows);
+    /// The account the token belongs to.
+    pub async fn get_credentials(
+        &mut self,
+        client: &ApiClient,
+    ) -> Result<&CredentialAccount, SyncError> {
+        if let Some(ref credentials) = self.credentials {
+            return Ok(credentials);
         }
 
-        tracing::info!("fetching all your follows");
-
-        // TODO: cache that too
-        let res: CredentialAccount = client
+        let credentials = client
             .get(
                 "/api/v1/accounts/verify_credentials",
                 Box::new(|builder| builder),
             )
             .await
-            .context("failed to get CredentialAccount")?
+            .sync_context("failed to get CredentialAccount")?
             .error_for_status()
-            .context("failed to get CredentialAccount")?
+            .sync_context("failed to get CredentialAccount")?
             .json()
             .await
-            .context("failed to get CredentialAccount")?;
+            .sync_context("failed to get CredentialAccount")?;
+
+        Ok(self.credentials.insert(credentials))
+    }
 
-        let mut url_opt = Some(format!("/api/v1/accounts/{}/following", res.id));
+    pub async fn get_follows(&mut self, client: &ApiClient) -> Result<&[Account], SyncError> {
+        if let Some(ref follows) = self.follows {
+            return Ok(follows);
+        }
+
+        tracing::info!("fetching all your follows");
+
+        let credentials = self.get_credentials(client).await?;
+        let mut url_opt = Some(format!("/api/v1/accounts/{}/following", credentials.id));
 
         let mut result = Vec::new();
+        let mut page = 0;
 
         while let Some(url) = url_opt.clone() {
+            page += 1;
+            client.progress.report(SyncProgress::FetchingFollows {
+                page,
+                count: result.len(),
+            });
+
             let res = client
                 .get(&url, Box::new(|builder| builder))
                 .await
-                .context("failed to get follows")?
+                .sync_context("failed to get follows")?
                 .error_for_status()
-                .context("failed to get follows")?;
+                .sync_context("failed to get follows")?;
 
             let next_url = api_helpers::get_next_link(&res);
-            let accounts: Vec<Account> =
-                res.json().await.context("failed to parse follows result")?;
+            let accounts: Vec<Account> = res
+                .json()
+                .await
+                .sync_context("failed to parse follows result")?;
 
             result.extend(accounts);
 
@@ -1,44 +1,152 @@
-use std::{env, sync::Arc};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{sync::Arc, time::Duration};
 
 use backoff::future::retry_notify;
 use backoff::ExponentialBackoff;
-use reqwest::{
-    header::{HeaderMap, HeaderValue},
-    Client, Method, RequestBuilder, Response, StatusCode,
-};
+use reqwest::{redirect, Certificate, Client, Method, Proxy, RequestBuilder, Response, StatusCode};
+use url::Host;
 
-use crate::error::ResponseError;
+use crate::error::{ResponseError, SyncError};
+use crate::hosts::HostState;
+use crate::instance::{is_public_ip, PublicResolver};
+use crate::metrics::METRICS;
+use crate::progress::{ProgressReporter, SyncProgress};
+
+/// How to connect to instances, see `HttpArgs`.
+#[derive(Clone)]
+pub struct ClientConfig {
+    pub connect_timeout: Duration,
+    pub read_timeout: Duration,
+    pub proxy: Option<Proxy>,
+    pub root_certificates: Vec<Certificate>,
+    /// URL or email address in the user agent, so instance admins can reach whoever runs the bot.
+    pub contact: Option<String>,
+    /// Hosts that are talked to over plain HTTP.
+    pub plaintext_hosts: Vec<String>,
+    /// Talk to every host over plain HTTP, for the deprecated `LIST_BOT_PLAINTEXT_HTTP`.
+    pub plaintext_all_hosts: bool,
+    /// How many redirects a request may follow. With 0, redirects are an error.
+    pub max_redirects: usize,
+    /// Refuse to connect to private and local addresses, including via redirects and DNS
+    /// changes. Set for the server, where anyone can enter a host. A proxy would resolve hosts
+    /// itself, so `proxy` is ignored and proxy environment variables aren't used.
+    pub public_only: bool,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            connect_timeout: Duration::from_secs(10),
+            read_timeout: Duration::from_secs(60),
+            proxy: None,
+            root_certificates: Vec::new(),
+            contact: None,
+            plaintext_hosts: Vec::new(),
+            plaintext_all_hosts: false,
+            max_redirects: 10,
+            public_only: false,
+        }
+    }
+}
+
+impl ClientConfig {
+    pub fn user_agent(&self) -> String {
+        let version = env!("CARGO_PKG_VERSION");
+        match &self.contact {
+            Some(contact) => format!("mastodon-list-bot/{} (+{})", version, contact),
+            None => format!("mastodon-list-bot/{}", version),
+        }
+    }
+
+    pub fn build_client(&self) -> Result<Client, ResponseError> {
+        let mut builder = Client::builder()
+            .use_rustls_tls()
+            .user_agent(self.user_agent())
+            .connect_timeout(self.connect_timeout)
+            .read_timeout(self.read_timeout)
+            .redirect(self.redirect_policy());
+
+        if self.public_only {
+            builder = builder.dns_resolver(Arc::new(PublicResolver)).no_proxy();
+        } else if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(proxy.clone());
+        }
+        for certificate in &self.root_certificates {
+            builder = builder.add_root_certificate(certificate.clone());
+        }
+
+        Ok(builder.build()?)
+    }
+
+    /// Follows up to `max_redirects`. With `public_only`, redirects to private IP addresses are
+    /// refused too: domains are covered by `PublicResolver`, but IP addresses don't go through
+    /// DNS.
+    fn redirect_policy(&self) -> redirect::Policy {
+        let public_only = self.public_only;
+        let max_redirects = self.max_redirects;
+        redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= max_redirects {
+                return attempt.error("too many redirects");
+            }
+
+            let ip = match attempt.url().host() {
+                Some(Host::Ipv4(ip)) => Some(ip.into()),
+                Some(Host::Ipv6(ip)) => Some(ip.into()),
+                _ => None,
+            };
+            if public_only && ip.is_some_and(|ip| !is_public_ip(ip)) {
+                return attempt.error("redirect to a private or local address");
+            }
+
+            attempt.follow()
+        })
+    }
+
+    /// `https://host`, or `http://host` for hosts in the plaintext allowlist.
+    pub fn base_url(&self, host: &str) -> String {
+        if self.plaintext_all_hosts || self.plaintext_hosts.iter().any(|allowed| allowed == host) {
+            format!("http://{}", host)
+        } else {
+            format!("https://{}", host)
+        }
+    }
+}
 
 pub struct ApiClient {
     pub client: Client,
     pub host: String,
+    token: Option<String>,
+    pub progress: ProgressReporter,
+    request_count: AtomicUsize,
+    host_state: Arc<HostState>,
 }
 
 impl ApiClient {
-    pub fn new(host: &str, token: Option<&str>) -> Result<Self, ResponseError> {
-        let mut headers = HeaderMap::new();
-        if let Some(token) = token {
-            headers.insert(
-                "Authorization",
-                HeaderValue::from_str(&format!("Bearer {}", token))?,
-            );
+    /// See `HostCoordinator::client`.
+    pub fn with_host(host_state: Arc<HostState>, token: Option<&str>) -> Self {
+        ApiClient {
+            client: host_state.client.clone(),
+            host: host_state.host.clone(),
+            token: token.map(str::to_owned),
+            progress: ProgressReporter::default(),
+            request_count: AtomicUsize::new(0),
+            host_state,
         }
+    }
 
-        headers.insert(
-            "User-Agent",
-            HeaderValue::from_str(&format!("mastodon-link-bot/{}", env!("CARGO_PKG_VERSION")))
-                .unwrap(),
-        );
+    /// Number of requests sent by this client, including retries.
+    pub fn request_count(&self) -> usize {
+        self.request_count.load(Ordering::Relaxed)
+    }
 
-        let client = Client::builder()
-            .use_rustls_tls()
-            .default_headers(headers)
-            .build()?;
+    pub fn with_progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = progress;
+        self
+    }
 
-        Ok(ApiClient {
-            client,
-            host: host.to_owned(),
-        })
+    /// Absolute URL for a route on the instance.
+    pub fn url(&self, route: &str) -> String {
+        format!("{}{}", self.host_state.base_url, route)
     }
 
     pub async fn request(
@@ -47,21 +155,9 @@ impl ApiClient {
         url: impl Into<String>,
         builder_fn: RequestBuilderFunction,
     ) -> Result<Response, reqwest::Error> {
-        let scheme = if matches!(
-            env::var("LIST_BOT_PLAINTEXT_HTTP")
-                .as_ref()
-                .map(String::as_str),
-            Ok("1")
-        ) {
-            "http"
-        } else {
-            "https"
-        };
-
         let mut url = url.into();
         if url.starts_with('/') {
-            let host = &self.host;
-            url = format!("{scheme}://{host}{url}");
+            url = self.url(&url);
         }
 
         let arc_builder_fn = Arc::new(builder_fn);
@@ -69,12 +165,25 @@ impl ApiClient {
         retry_notify(
             ExponentialBackoff::default(),
             || async {
-                let request_builder = self.client.request(method.clone(), url.clone());
+                self.host_state.wait_for_rate_limit().await;
+                self.request_count.fetch_add(1, Ordering::Relaxed);
+                let mut request_builder = self.client.request(method.clone(), url.clone());
+                if let Some(token) = &self.token {
+                    request_builder = request_builder.bearer_auth(token);
+                }
+
+                let response = (arc_builder_fn.clone())(request_builder).send().await;
+
+                let status = match &response {
+                    Ok(response) => response.status().as_u16().to_string(),
+                    Err(_) => "error".to_owned(),
+                };
+                METRICS
+                    .api_requests
+                    .with_label_values(&[METRICS.host_label(&self.host), &status])
+                    .inc();
 
-                let response = (arc_builder_fn.clone())(request_builder)
-                    .send()
-                    .await
-                    .map_err(backoff::Error::permanent)?;
+                let response = response.map_err(backoff::Error::permanent)?;
 
                 if response.status() == StatusCode::TOO_MANY_REQUESTS {
                     return Err(backoff::Error::transient(
@@ -84,12 +193,20 @@ impl ApiClient {
 
                 Ok(response)
             },
-            |_err, dur| {
+            |_err, dur: Duration| {
                 tracing::warn!(
                     "[{}] encountered rate limit, backing off for {:?}",
                     self.host,
                     dur
                 );
+                self.host_state.pause_for(dur);
+                METRICS
+                    .rate_limit_waits
+                    .with_label_values(&[METRICS.host_label(&self.host)])
+                    .inc();
+                self.progress.report(SyncProgress::RateLimited {
+                    wait_secs: dur.as_secs(),
+                });
             },
         )
         .await
@@ -111,6 +228,14 @@ impl ApiClient {
         self.request(Method::POST, route, builder_fn).await
     }
 
+    pub async fn put(
+        &self,
+        route: &str,
+        builder_fn: RequestBuilderFunction,
+    ) -> Result<Response, reqwest::Error> {
+        self.request(Method::PUT, route, builder_fn).await
+    }
+
     pub async fn delete(
         &self,
         route: &str,
@@ -121,3 +246,51 @@ impl ApiClient {
 }
 
 type RequestBuilderFunction = Box<dyn Send + Sync + Fn(RequestBuilder) -> RequestBuilder>;
+
+pub trait SyncContext<T> {
+    /// Like `anyhow::Context`, but classifies the error into a `SyncError`.
+    fn sync_context(self, context: &str) -> Result<T, SyncError>;
+
+    /// Like `sync_context`, for requests on a specific list, where a 404 means that the list was
+    /// deleted rather than that the server doesn't support the endpoint.
+    fn list_context(self, list_id: &str, context: &str) -> Result<T, SyncError>;
+}
+
+impl<T> SyncContext<T> for Result<T, reqwest::Error> {
+    fn sync_context(self, context: &str) -> Result<T, SyncError> {
+        self.map_err(|e| SyncError::from_reqwest(context, e))
+    }
+
+    fn list_context(self, list_id: &str, context: &str) -> Result<T, SyncError> {
+        self.map_err(|e| match e.status() {
+            Some(StatusCode::NOT_FOUND | StatusCode::GONE) => SyncError::ListNotFound {
+                list_id: list_id.to_owned(),
+            },
+            _ => SyncError::from_reqwest(context, e),
+        })
+    }
+}
+
+#[test]
+fn client_config() {
+    let config = ClientConfig {
+        contact: Some("https://example.com/bot".to_owned()),
+        plaintext_hosts: vec!["localhost:3000".to_owned()],
+        ..ClientConfig::default()
+    };
+    assert_eq!(
+        config.user_agent(),
+        format!(
+            "mastodon-list-bot/{} (+https://example.com/bot)",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+    assert_eq!(config.base_url("localhost:3000"), "http://localhost:3000");
+    assert_eq!(config.base_url("example.com"), "https://example.com");
+
+    let config = ClientConfig {
+        plaintext_all_hosts: true,
+        ..ClientConfig::default()
+    };
+    assert_eq!(config.base_url("example.com"), "http://example.com");
+}
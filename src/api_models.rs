@@ -21,6 +21,8 @@ where
 #[derive(Deserialize, Debug, Clone)]
 pub struct Account {
     pub id: String,
+    #[serde(default)]
+    pub acct: String,
     #[serde(deserialize_with = "date_deserialize")]
     pub last_status_at: Option<NaiveDate>,
 }
@@ -38,6 +40,36 @@ pub struct Relationship {
     pub followed_by: bool,
 }
 
+/// `/.well-known/nodeinfo`
+#[derive(Deserialize, Debug)]
+pub struct NodeInfoLinks {
+    pub links: Vec<NodeInfoLink>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NodeInfoLink {
+    pub rel: String,
+    pub href: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NodeInfo {
+    pub software: NodeInfoSoftware,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NodeInfoSoftware {
+    pub name: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+/// `/api/v2/instance`, only what we need to tell whether the server speaks the Mastodon API.
+#[derive(Deserialize, Debug)]
+pub struct Instance {
+    pub version: String,
+}
+
 #[test]
 fn test_deserialize_account() {
     let _account: Account = serde_json::from_str(
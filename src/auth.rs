@@ -1,12 +1,30 @@
 use axum::async_trait;
 use axum::extract::FromRequestParts;
 use axum::http::{request::Parts, StatusCode};
+use serde::{Deserialize, Serialize};
 use tower_sessions::Session;
 
 use crate::AccountPk;
 use crate::ResponseError;
 
 pub const SESSION_COOKIE_KEY: &str = "auth";
+/// Session key for the `PendingLogin` of an OAuth flow in progress.
+pub const OAUTH_SESSION_KEY: &str = "oauth";
+pub const OAUTH_SCOPES: &str = "read:follows read:lists read:accounts write:lists";
+
+/// Kept in the session between redirecting to the instance and the instance redirecting back.
+#[derive(Deserialize, Serialize)]
+pub struct PendingLogin {
+    pub host: String,
+    /// The app the user was sent to authorize. The code can only be exchanged with this app's
+    /// credentials, even if the stored app for the host was replaced in the meantime.
+    pub client_id: String,
+    /// Random value that the instance has to echo back, so that nobody else can complete a login
+    /// in this session.
+    pub state: String,
+    /// PKCE code verifier, see RFC 7636.
+    pub code_verifier: String,
+}
 
 pub struct LoggedIn {
     pub account: Option<AccountPk>,
@@ -1,14 +1,142 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use clap::Parser;
+use serde::Deserialize;
+
+use crate::api_client::ClientConfig;
+use crate::crypto::{CryptoError, TokenCipher};
+use crate::safety::SafetyLimits;
+use crate::schedule::Schedule;
 
 #[derive(Debug, Parser)]
 pub struct Cli {
     #[clap(subcommand)]
     pub subcommand: Subcommand,
+    #[clap(flatten)]
+    pub http: HttpArgs,
+}
+
+/// How to connect to instances. Accepted by every subcommand.
+#[derive(Debug, clap::Args)]
+pub struct HttpArgs {
+    /// Seconds to wait for a connection to an instance.
+    #[arg(
+        long,
+        env = "LIST_BOT_CONNECT_TIMEOUT",
+        default_value = "10",
+        global = true
+    )]
+    pub connect_timeout: u64,
+    /// Seconds to wait for an instance to send more of its response.
+    #[arg(
+        long,
+        env = "LIST_BOT_READ_TIMEOUT",
+        default_value = "60",
+        global = true
+    )]
+    pub read_timeout: u64,
+    /// Send all requests through this HTTP(S) proxy, e.g. `http://proxy.internal:3128`.
+    #[arg(long, env = "LIST_BOT_PROXY", global = true)]
+    pub proxy: Option<String>,
+    /// PEM files with root certificates to trust in addition to the built-in ones,
+    /// comma-separated.
+    #[arg(
+        long,
+        env = "LIST_BOT_ROOT_CERTIFICATES",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub root_certificates: Vec<PathBuf>,
+    /// URL or email address added to the user agent, so instance admins can reach you.
+    #[arg(long, env = "LIST_BOT_CONTACT", global = true)]
+    pub contact: Option<String>,
+    /// Hosts to talk to over plain HTTP instead of HTTPS, comma-separated. Only useful for
+    /// development.
+    #[arg(
+        long,
+        env = "LIST_BOT_PLAINTEXT_HOSTS",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub plaintext_hosts: Vec<String>,
+    /// Deprecated, use `--plaintext-hosts`. Talks to every host over plain HTTP.
+    #[arg(long, env = "LIST_BOT_PLAINTEXT_HTTP", hide = true, global = true)]
+    pub plaintext_http: bool,
+    /// How many redirects to follow per request. 0 refuses all redirects.
+    #[arg(
+        long,
+        env = "LIST_BOT_MAX_REDIRECTS",
+        default_value = "10",
+        global = true
+    )]
+    pub max_redirects: usize,
+}
+
+impl HttpArgs {
+    /// With `public_only`, see `ClientConfig::public_only`. A proxy resolves hosts itself, so it
+    /// can't be used then.
+    pub fn client_config(&self, public_only: bool) -> Result<ClientConfig, ConfigError> {
+        if public_only && self.proxy.is_some() {
+            return Err(ConfigError::ProxyWithPublicOnly);
+        }
+
+        let proxy = self
+            .proxy
+            .as_deref()
+            .map(reqwest::Proxy::all)
+            .transpose()
+            .map_err(|source| ConfigError::Proxy {
+                proxy: self.proxy.clone().unwrap_or_default(),
+                source,
+            })?;
+
+        let mut root_certificates = Vec::new();
+        for path in &self.root_certificates {
+            let pem = std::fs::read(path).map_err(|source| ConfigError::Read {
+                path: path.clone(),
+                source,
+            })?;
+            let certificates = reqwest::Certificate::from_pem_bundle(&pem).map_err(|source| {
+                ConfigError::Certificate {
+                    path: path.clone(),
+                    source,
+                }
+            })?;
+            root_certificates.extend(certificates);
+        }
+
+        if self.plaintext_http {
+            tracing::warn!(
+                "LIST_BOT_PLAINTEXT_HTTP is deprecated and talks to every host over plain HTTP, \
+                 use LIST_BOT_PLAINTEXT_HOSTS to list the hosts instead"
+            );
+        }
+
+        Ok(ClientConfig {
+            connect_timeout: Duration::from_secs(self.connect_timeout),
+            read_timeout: Duration::from_secs(self.read_timeout),
+            proxy,
+            root_certificates,
+            contact: self.contact.clone(),
+            plaintext_hosts: self.plaintext_hosts.clone(),
+            plaintext_all_hosts: self.plaintext_http,
+            max_redirects: self.max_redirects,
+            public_only,
+        })
+    }
 }
 
 #[derive(Debug, Parser)]
 pub enum Subcommand {
     RunOnce(RunOnce),
+    Run(Run),
+    Daemon(Daemon),
+    Explain(Explain),
+    Validate(Validate),
+    #[clap(subcommand)]
+    Snapshot(Snapshot),
     Serve(Server),
 }
 
@@ -16,8 +144,147 @@ pub enum Subcommand {
 pub struct RunOnce {
     #[arg(long)]
     pub host: String,
+    #[arg(long, env = "LIST_BOT_TOKEN", hide_env_values = true)]
+    pub token: String,
+    /// With `json`, print a report of what was changed to stdout.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+    #[clap(flatten)]
+    pub snapshots: SnapshotDirArgs,
+    #[clap(flatten)]
+    pub safety: SafetyArgs,
+    #[clap(flatten)]
+    pub incremental: IncrementalArgs,
+    /// Sync even if it exceeds the safety limits.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SnapshotDirArgs {
+    /// Directory where list memberships are saved before a sync changes them.
+    #[arg(long, env = "LIST_BOT_SNAPSHOT_DIR", default_value = "snapshots")]
+    pub snapshot_dir: PathBuf,
+}
+
+/// See `ListRecord`.
+#[derive(Debug, clap::Args)]
+pub struct IncrementalArgs {
+    /// Skip fetching the members of lists that match the members recorded at the last sync.
+    /// Members are still fetched when a list needs changes, and at least weekly.
+    #[arg(long, env = "LIST_BOT_INCREMENTAL")]
+    pub incremental: bool,
+}
+
+/// See `SafetyLimits`.
+#[derive(Debug, clap::Args)]
+pub struct SafetyArgs {
+    /// Skip a list if the sync would remove more than this fraction of its members.
+    #[arg(long, env = "LIST_BOT_MAX_REMOVED_FRACTION", default_value = "0.5")]
+    pub max_removed_fraction: f64,
+    /// Refuse to sync if the number of follows dropped by more than this fraction since the last
+    /// successful sync.
+    #[arg(long, env = "LIST_BOT_MAX_FOLLOWS_DROP", default_value = "0.5")]
+    pub max_follows_drop: f64,
+}
+
+impl SafetyArgs {
+    pub fn limits(&self, force: bool) -> SafetyLimits {
+        SafetyLimits {
+            max_removed_fraction: self.max_removed_fraction,
+            max_follows_drop: self.max_follows_drop,
+            force,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// Sync all accounts listed in a config file once.
+#[derive(Debug, Parser)]
+pub struct Run {
+    /// Path to a TOML file with one `[[account]]` section per account.
+    #[arg(long, env = "LIST_BOT_CONFIG")]
+    pub config: PathBuf,
+    #[clap(flatten)]
+    pub snapshots: SnapshotDirArgs,
+    #[clap(flatten)]
+    pub safety: SafetyArgs,
+    #[clap(flatten)]
+    pub incremental: IncrementalArgs,
+    /// Sync even if it exceeds the safety limits.
     #[arg(long)]
+    pub force: bool,
+}
+
+/// Keep syncing the accounts from a config file on their schedules, without the web frontend.
+#[derive(Debug, Parser)]
+pub struct Daemon {
+    /// Path to a TOML file with one `[[account]]` section per account.
+    #[arg(long, env = "LIST_BOT_CONFIG")]
+    pub config: PathBuf,
+    /// Seconds between checks for accounts that are due.
+    #[arg(long, default_value = "300")]
+    pub interval: u64,
+    #[clap(flatten)]
+    pub snapshots: SnapshotDirArgs,
+    #[clap(flatten)]
+    pub safety: SafetyArgs,
+    #[clap(flatten)]
+    pub incremental: IncrementalArgs,
+}
+
+/// Show why an account is or isn't in a managed list.
+#[derive(Debug, Parser)]
+pub struct Explain {
+    #[arg(long)]
+    pub host: String,
+    #[arg(long, env = "LIST_BOT_TOKEN", hide_env_values = true)]
     pub token: String,
+    /// ID of the list.
+    #[arg(long)]
+    pub list: String,
+    /// The account to check, e.g. `@user@example.com`.
+    #[arg(long)]
+    pub account: String,
+}
+
+/// Check a filter expression, or the titles of all lists on an account.
+#[derive(Debug, Parser)]
+pub struct Validate {
+    /// Filter expression or list title, e.g. `"#last_status_at>1d & mutuals"`.
+    #[arg(required_unless_present = "lists")]
+    pub expression: Option<String>,
+    /// Fetch the lists of the account and report which are managed, ignored or broken.
+    #[arg(long, conflicts_with = "expression", requires = "host")]
+    pub lists: bool,
+    #[arg(long)]
+    pub host: Option<String>,
+    #[arg(long, env = "LIST_BOT_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+}
+
+/// Inspect and restore the list memberships saved by the CLI.
+#[derive(Debug, Parser)]
+pub enum Snapshot {
+    /// Show all saved snapshots, oldest first.
+    List {
+        #[clap(flatten)]
+        snapshots: SnapshotDirArgs,
+    },
+    /// Put a list's members back to the state of a snapshot.
+    Restore {
+        /// ID of the snapshot, as shown by `snapshot list`.
+        id: String,
+        #[clap(flatten)]
+        snapshots: SnapshotDirArgs,
+        #[arg(long, env = "LIST_BOT_TOKEN", hide_env_values = true)]
+        token: String,
+    },
 }
 
 #[derive(Debug, Parser)]
@@ -28,4 +295,237 @@ pub struct Server {
     pub addr: String,
     #[arg(long, default_value = "3001")]
     pub port: u16,
+    /// URL under which users reach the server, e.g. `https://list-bot.example.com`. Used for the
+    /// OAuth redirect URI that is registered with instances.
+    #[arg(long, env = "LIST_BOT_PUBLIC_URL")]
+    pub public_url: String,
+    /// Base64-encoded 32-byte key used to encrypt access tokens in the database.
+    #[arg(
+        long,
+        env = "LIST_BOT_TOKEN_KEY",
+        hide_env_values = true,
+        required_unless_present = "token_key_file"
+    )]
+    pub token_key: Option<String>,
+    /// Read the token encryption key from a file instead.
+    #[arg(long, env = "LIST_BOT_TOKEN_KEY_FILE", conflicts_with = "token_key")]
+    pub token_key_file: Option<PathBuf>,
+    /// Previous token encryption keys, comma-separated. Tokens encrypted with these keys are
+    /// re-encrypted with the current key on startup.
+    #[arg(
+        long,
+        env = "LIST_BOT_OLD_TOKEN_KEYS",
+        hide_env_values = true,
+        value_delimiter = ','
+    )]
+    pub old_token_keys: Vec<String>,
+    /// Allow logging in with instances on private or local addresses. Only useful for
+    /// development.
+    #[arg(long, env = "LIST_BOT_ALLOW_PRIVATE_HOSTS")]
+    pub allow_private_hosts: bool,
+    #[clap(flatten)]
+    pub safety: SafetyArgs,
+    #[clap(flatten)]
+    pub incremental: IncrementalArgs,
+}
+
+impl Server {
+    pub fn token_cipher(&self) -> Result<TokenCipher, CryptoError> {
+        match (&self.token_key, &self.token_key_file) {
+            (Some(key), _) => TokenCipher::new(key, &self.old_token_keys),
+            (None, Some(path)) => TokenCipher::from_key_file(path, &self.old_token_keys),
+            (None, None) => Err(CryptoError::InvalidKey("no token key given".to_owned())),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("failed to read {path}: {source}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to parse {path}: {source}")]
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    #[error("{0}: exactly one of token, token_file and token_env must be set")]
+    Token(String),
+    #[error("{0}: another account has the same name, set a distinct `name` for one of them")]
+    DuplicateName(String),
+    #[error("{0}: invalid schedule: {1}")]
+    Schedule(String, String),
+    #[error("{account}: environment variable {var} is not set")]
+    TokenEnv { account: String, var: String },
+    #[error("invalid proxy {proxy}: {source}")]
+    Proxy {
+        proxy: String,
+        source: reqwest::Error,
+    },
+    #[error(
+        "--proxy can't be used while logins are restricted to public addresses, since the proxy \
+         resolves hosts itself; pass --allow-private-hosts to use a proxy anyway"
+    )]
+    ProxyWithPublicOnly,
+    #[error("failed to load certificates from {path}: {source}")]
+    Certificate {
+        path: PathBuf,
+        source: reqwest::Error,
+    },
+}
+
+/// Accounts for the `run` subcommand.
+///
+/// ```toml
+/// [[account]]
+/// host = "mastodon.social"
+/// token_file = "/run/secrets/mastodon-social"
+///
+/// [[account]]
+/// host = "example.com"
+/// token_env = "EXAMPLE_COM_TOKEN"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountsFile {
+    #[serde(rename = "account", default)]
+    pub accounts: Vec<AccountConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountConfig {
+    pub host: String,
+    /// Shown in logs and summaries instead of the host.
+    pub name: Option<String>,
+    pub token: Option<String>,
+    pub token_file: Option<PathBuf>,
+    pub token_env: Option<String>,
+    /// Only used by the daemon. Same format as in the web frontend: `6h`, `daily`, `weekly` or
+    /// `cron:<expression>`.
+    pub schedule: Option<String>,
+    pub preferred_hour: Option<u32>,
+}
+
+impl AccountsFile {
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let contents = std::fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.to_owned(),
+            source,
+        })?;
+        let file: Self = toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.to_owned(),
+            source,
+        })?;
+        file.check_names()?;
+        Ok(file)
+    }
+
+    /// Names identify accounts in the daemon state, so they have to be unique.
+    fn check_names(&self) -> Result<(), ConfigError> {
+        let mut names = HashSet::new();
+        for account in &self.accounts {
+            if !names.insert(account.name()) {
+                return Err(ConfigError::DuplicateName(account.name().to_owned()));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl AccountConfig {
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.host)
+    }
+
+    pub fn schedule(&self) -> Result<Schedule, ConfigError> {
+        let frequency = match &self.schedule {
+            Some(frequency) => frequency.parse(),
+            None => Ok(Schedule::default().frequency),
+        };
+
+        frequency
+            .and_then(|frequency| Schedule::new(frequency, self.preferred_hour))
+            .map_err(|e| ConfigError::Schedule(self.name().to_owned(), e))
+    }
+
+    pub fn token(&self) -> Result<String, ConfigError> {
+        match (&self.token, &self.token_file, &self.token_env) {
+            (Some(token), None, None) => Ok(token.clone()),
+            (None, Some(path), None) => std::fs::read_to_string(path)
+                .map(|token| token.trim().to_owned())
+                .map_err(|source| ConfigError::Read {
+                    path: path.clone(),
+                    source,
+                }),
+            (None, None, Some(var)) => std::env::var(var).map_err(|_| ConfigError::TokenEnv {
+                account: self.name().to_owned(),
+                var: var.clone(),
+            }),
+            _ => Err(ConfigError::Token(self.name().to_owned())),
+        }
+    }
+}
+
+#[test]
+fn parse_accounts_file() {
+    let file: AccountsFile = toml::from_str(
+        r#"
+        [[account]]
+        host = "mastodon.social"
+        token = "secret"
+
+        [[account]]
+        host = "example.com"
+        name = "work"
+        token_env = "LIST_BOT_TEST_TOKEN_THAT_IS_NOT_SET"
+        schedule = "weekly"
+        preferred_hour = 25
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(file.accounts.len(), 2);
+    assert_eq!(file.accounts[0].token().unwrap(), "secret");
+    assert_eq!(file.accounts[1].name(), "work");
+    assert!(file.accounts[1].token().is_err());
+    assert_eq!(file.accounts[0].schedule().unwrap(), Schedule::default());
+    assert!(file.accounts[1].schedule().is_err());
+    assert!(file.check_names().is_ok());
+
+    let file: AccountsFile = toml::from_str(
+        r#"
+        [[account]]
+        host = "mastodon.social"
+        token = "secret"
+
+        [[account]]
+        host = "mastodon.social"
+        token = "other secret"
+        "#,
+    )
+    .unwrap();
+    assert!(matches!(
+        file.check_names(),
+        Err(ConfigError::DuplicateName(name)) if name == "mastodon.social"
+    ));
+}
+
+#[test]
+fn proxy_with_public_only() {
+    let cli = Cli::try_parse_from([
+        "mastodon-list-bot",
+        "--proxy",
+        "http://proxy.internal:3128",
+        "validate",
+        "#mutuals",
+    ])
+    .unwrap();
+    assert!(matches!(
+        cli.http.client_config(true),
+        Err(ConfigError::ProxyWithPublicOnly)
+    ));
+    assert!(cli.http.client_config(false).unwrap().proxy.is_some());
 }
@@ -0,0 +1,234 @@
+use std::fs;
+use std::path::Path;
+
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+
+// stored tokens look like "enc:v1:<key id>:<base64 nonce+ciphertext>". anything without that
+// prefix is a plaintext token from before encryption was introduced.
+const TOKEN_PREFIX: &str = "enc:v1:";
+const KEY_LEN: usize = 32;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CryptoError {
+    #[error("invalid key: {0}")]
+    InvalidKey(String),
+    #[error("failed to read key file: {0}")]
+    KeyFile(#[from] std::io::Error),
+    #[error("token was encrypted with unknown key {0}")]
+    UnknownKey(String),
+    #[error("malformed encrypted token")]
+    Malformed,
+    #[error("failed to encrypt token")]
+    Encrypt,
+    #[error("failed to generate random bytes")]
+    Random,
+    #[error("failed to decrypt token")]
+    Decrypt,
+}
+
+struct TokenKey {
+    id: String,
+    key: LessSafeKey,
+}
+
+impl TokenKey {
+    fn parse(encoded: &str) -> Result<Self, CryptoError> {
+        let bytes = data_encoding::BASE64
+            .decode(encoded.trim().as_bytes())
+            .map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
+
+        if bytes.len() != KEY_LEN {
+            return Err(CryptoError::InvalidKey(format!(
+                "expected {} bytes, got {}",
+                KEY_LEN,
+                bytes.len()
+            )));
+        }
+
+        let id = data_encoding::HEXLOWER.encode(&digest(&SHA256, &bytes).as_ref()[..4]);
+        let key = UnboundKey::new(&CHACHA20_POLY1305, &bytes)
+            .map_err(|_| CryptoError::InvalidKey("rejected by cipher".to_owned()))?;
+
+        Ok(TokenKey {
+            id,
+            key: LessSafeKey::new(key),
+        })
+    }
+}
+
+/// Encrypts access tokens before they are written to the database.
+///
+/// The first key is used for encryption, all keys are tried for decryption. To rotate keys, pass
+/// the new key as primary and the old one as an additional key, then restart the server.
+pub struct TokenCipher {
+    keys: Vec<TokenKey>,
+    rng: SystemRandom,
+}
+
+impl TokenCipher {
+    pub fn new(primary_key: &str, old_keys: &[String]) -> Result<Self, CryptoError> {
+        let mut keys = vec![TokenKey::parse(primary_key)?];
+        for old_key in old_keys {
+            keys.push(TokenKey::parse(old_key)?);
+        }
+
+        Ok(TokenCipher {
+            keys,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    pub fn from_key_file(path: &Path, old_keys: &[String]) -> Result<Self, CryptoError> {
+        let primary_key = fs::read_to_string(path)?;
+        Self::new(&primary_key, old_keys)
+    }
+
+    /// Encrypt a token. `context` binds the ciphertext to a single account, so that tokens cannot
+    /// be swapped between rows.
+    pub fn encrypt(&self, token: &str, context: &str) -> Result<String, CryptoError> {
+        let primary = &self.keys[0];
+
+        let mut nonce = [0u8; NONCE_LEN];
+        self.rng
+            .fill(&mut nonce)
+            .map_err(|_| CryptoError::Encrypt)?;
+
+        let mut in_out = token.as_bytes().to_vec();
+        primary
+            .key
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(context.as_bytes()),
+                &mut in_out,
+            )
+            .map_err(|_| CryptoError::Encrypt)?;
+
+        let mut payload = nonce.to_vec();
+        payload.extend(in_out);
+
+        Ok(format!(
+            "{}{}:{}",
+            TOKEN_PREFIX,
+            primary.id,
+            data_encoding::BASE64URL_NOPAD.encode(&payload)
+        ))
+    }
+
+    pub fn decrypt(&self, stored: &str, context: &str) -> Result<String, CryptoError> {
+        let Some(rest) = stored.strip_prefix(TOKEN_PREFIX) else {
+            // legacy plaintext token, will be encrypted on next startup
+            return Ok(stored.to_owned());
+        };
+
+        let (key_id, payload) = rest.split_once(':').ok_or(CryptoError::Malformed)?;
+        let key = self
+            .keys
+            .iter()
+            .find(|k| k.id == key_id)
+            .ok_or_else(|| CryptoError::UnknownKey(key_id.to_owned()))?;
+
+        let mut payload = data_encoding::BASE64URL_NOPAD
+            .decode(payload.as_bytes())
+            .map_err(|_| CryptoError::Malformed)?;
+
+        if payload.len() < NONCE_LEN {
+            return Err(CryptoError::Malformed);
+        }
+
+        let mut in_out = payload.split_off(NONCE_LEN);
+        let nonce =
+            Nonce::try_assume_unique_for_key(&payload).map_err(|_| CryptoError::Malformed)?;
+        let plaintext = key
+            .key
+            .open_in_place(nonce, Aad::from(context.as_bytes()), &mut in_out)
+            .map_err(|_| CryptoError::Decrypt)?;
+
+        String::from_utf8(plaintext.to_vec()).map_err(|_| CryptoError::Malformed)
+    }
+
+    /// Whether the stored token needs to be re-encrypted with the primary key, either because it
+    /// is still plaintext or because it was encrypted with an old key.
+    pub fn needs_reencrypt(&self, stored: &str) -> bool {
+        match stored.strip_prefix(TOKEN_PREFIX) {
+            Some(rest) => !rest.starts_with(&format!("{}:", self.keys[0].id)),
+            None => true,
+        }
+    }
+}
+
+/// A random token for use in URLs and headers, e.g. API tokens.
+pub fn random_token(prefix: &str) -> Result<String, CryptoError> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .map_err(|_| CryptoError::Random)?;
+    Ok(format!(
+        "{}{}",
+        prefix,
+        data_encoding::BASE64URL_NOPAD.encode(&bytes)
+    ))
+}
+
+/// Hash a high-entropy token for storage. No salt needed since tokens are random.
+pub fn hash_token(token: &str) -> String {
+    data_encoding::HEXLOWER.encode(digest(&SHA256, token.as_bytes()).as_ref())
+}
+
+/// The PKCE `S256` code challenge for a code verifier, see RFC 7636.
+pub fn pkce_challenge(verifier: &str) -> String {
+    data_encoding::BASE64URL_NOPAD.encode(digest(&SHA256, verifier.as_bytes()).as_ref())
+}
+
+#[cfg(test)]
+const TEST_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+#[cfg(test)]
+const TEST_KEY_2: &str = "HxwdHhobGBkWFxQVEhMQEQ4PDA0KCwgJBgcEBQIDAAE=";
+
+#[test]
+fn roundtrip() {
+    let cipher = TokenCipher::new(TEST_KEY, &[]).unwrap();
+    let stored = cipher.encrypt("secret", "user@example.com").unwrap();
+    assert!(stored.starts_with(TOKEN_PREFIX));
+    assert!(!stored.contains("secret"));
+    assert!(!cipher.needs_reencrypt(&stored));
+    assert_eq!(
+        cipher.decrypt(&stored, "user@example.com").unwrap(),
+        "secret"
+    );
+    assert!(cipher.decrypt(&stored, "other@example.com").is_err());
+}
+
+#[test]
+fn legacy_plaintext() {
+    let cipher = TokenCipher::new(TEST_KEY, &[]).unwrap();
+    assert!(cipher.needs_reencrypt("secret"));
+    assert_eq!(
+        cipher.decrypt("secret", "user@example.com").unwrap(),
+        "secret"
+    );
+}
+
+#[test]
+fn rotation() {
+    let old = TokenCipher::new(TEST_KEY, &[]).unwrap();
+    let stored = old.encrypt("secret", "user@example.com").unwrap();
+
+    let new = TokenCipher::new(TEST_KEY_2, &[TEST_KEY.to_owned()]).unwrap();
+    assert!(new.needs_reencrypt(&stored));
+    assert_eq!(new.decrypt(&stored, "user@example.com").unwrap(), "secret");
+
+    let restored = new.encrypt("secret", "user@example.com").unwrap();
+    assert!(!new.needs_reencrypt(&restored));
+    assert!(old.decrypt(&restored, "user@example.com").is_err());
+}
+
+#[test]
+fn pkce() {
+    // example from RFC 7636, appendix B
+    assert_eq!(
+        pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+        "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    );
+}
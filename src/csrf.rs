@@ -0,0 +1,118 @@
+use axum::async_trait;
+use axum::body::{to_bytes, Body};
+use axum::extract::{FromRequestParts, Request};
+use axum::http::{request::Parts, Method};
+use axum::middleware::Next;
+use axum::response::Response;
+use maud::Markup;
+use serde::Deserialize;
+use tower_sessions::Session;
+
+use crate::crypto::{hash_token, random_token};
+use crate::error::ResponseError;
+
+const CSRF_SESSION_KEY: &str = "csrf";
+const CSRF_HEADER: &str = "x-csrf-token";
+const CSRF_FIELD: &str = "csrf_token";
+const MAX_FORM_SIZE: usize = 64 * 1024;
+/// Exempt from the CSRF check, see `verify`.
+const LOGIN_PATH: &str = "/account/login";
+
+/// The CSRF token of the current session, created on first use. Every state-changing request has
+/// to send it back, either as a `csrf_token` form field or as `X-CSRF-Token` header.
+///
+/// Visitors without a session don't get a token, since storing one would create a session for
+/// every anonymous page view. The only form they see is the login form, which is exempt.
+pub struct CsrfToken(Option<String>);
+
+impl CsrfToken {
+    /// Hidden form field, to be put into every `<form method="post">`.
+    pub fn field(&self) -> Markup {
+        maud::html! {
+            @if let Some(token) = &self.0 {
+                input type="hidden" name=(CSRF_FIELD) value=(token);
+            }
+        }
+    }
+
+    /// Value for `data-hx-headers`, for htmx requests that don't submit a form.
+    pub fn htmx_headers(&self) -> String {
+        match &self.0 {
+            Some(token) => serde_json::json!({ "X-CSRF-Token": token }).to_string(),
+            None => "{}".to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CsrfToken
+where
+    S: Send + Sync,
+{
+    type Rejection = ResponseError;
+
+    async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let session = Session::from_request_parts(req, state)
+            .await
+            .map_err(|_| ResponseError::InvalidCsrfToken)?;
+
+        if let Some(token) = session.get(CSRF_SESSION_KEY).await? {
+            return Ok(CsrfToken(Some(token)));
+        }
+
+        if session.id().is_none() {
+            return Ok(CsrfToken(None));
+        }
+
+        let token = random_token("")?;
+        session.insert(CSRF_SESSION_KEY, &token).await?;
+        Ok(CsrfToken(Some(token)))
+    }
+}
+
+#[derive(Deserialize)]
+struct CsrfForm {
+    csrf_token: Option<String>,
+}
+
+/// Middleware that rejects state-changing requests without the session's CSRF token. Requests
+/// authenticated with an `Authorization` header don't rely on cookies, and are let through. So is
+/// the login form: it only starts an OAuth flow, which is bound to the browser's session by its
+/// `state` parameter.
+pub async fn verify(session: Session, req: Request, next: Next) -> Result<Response, ResponseError> {
+    if matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+        || req.headers().contains_key("authorization")
+        || req.uri().path() == LOGIN_PATH
+    {
+        return Ok(next.run(req).await);
+    }
+
+    let expected: Option<String> = session.get(CSRF_SESSION_KEY).await?;
+
+    let header = req
+        .headers()
+        .get(CSRF_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+
+    let (parts, body) = req.into_parts();
+    let bytes = to_bytes(body, MAX_FORM_SIZE)
+        .await
+        .map_err(|e| ResponseError::BadRequest(format!("failed to read request body: {}", e)))?;
+
+    let given = header.or_else(|| {
+        serde_urlencoded::from_bytes::<CsrfForm>(&bytes)
+            .ok()
+            .and_then(|form| form.csrf_token)
+    });
+
+    // compare hashes, so that timing doesn't leak the expected token
+    match (expected, given) {
+        (Some(expected), Some(given)) if hash_token(&expected) == hash_token(&given) => {}
+        _ => return Err(ResponseError::InvalidCsrfToken),
+    }
+
+    Ok(next
+        .run(Request::from_parts(parts, Body::from(bytes)))
+        .await)
+}
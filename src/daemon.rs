@@ -0,0 +1,259 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Error};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::config::{AccountConfig, AccountsFile};
+use crate::runner::{run_once, RunOptions};
+use crate::schedule::Schedule;
+use crate::store::{retry_delay, MAX_FAILURES};
+
+/// Counterpart of the scheduling columns in the `accounts` table. Saved to the state file after
+/// every attempt, so that schedules and backoff survive restarts.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct AccountState {
+    last_success_at: Option<NaiveDateTime>,
+    next_attempt_at: Option<NaiveDateTime>,
+    failure_count: i64,
+}
+
+struct DaemonAccount<'a> {
+    config: &'a AccountConfig,
+    schedule: Schedule,
+    state: AccountState,
+}
+
+impl DaemonAccount<'_> {
+    fn is_due(&self, now: NaiveDateTime) -> bool {
+        self.state.failure_count < MAX_FAILURES
+            && self.state.next_attempt_at.is_none_or(|at| at <= now)
+    }
+}
+
+/// Account states by `AccountConfig::name`.
+type DaemonState = BTreeMap<String, AccountState>;
+
+fn load_state(path: &Path) -> Result<DaemonState, Error> {
+    match std::fs::read(path) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .with_context(|| format!("failed to parse {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(DaemonState::new()),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+fn save_state(path: &Path, accounts: &[DaemonAccount]) -> Result<(), Error> {
+    let state: DaemonState = accounts
+        .iter()
+        .map(|account| (account.config.name().to_owned(), account.state.clone()))
+        .collect();
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    // write to a temporary file first, so that a crash can't leave a truncated state file
+    let tmp_path = path.with_extension("json.tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec_pretty(&state)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Sync the accounts from the config file whenever they are due, until SIGTERM or Ctrl-C. A sync
+/// that is running when the signal arrives finishes its current list first. Scheduling state is
+/// checkpointed to `state_path`. Accounts that were paused after too many failures are resumed
+/// on startup.
+pub async fn run(
+    config: &AccountsFile,
+    interval: Duration,
+    state_path: &Path,
+    options: RunOptions,
+) -> Result<(), Error> {
+    let mut saved_state = load_state(state_path)?;
+    let mut accounts = config
+        .accounts
+        .iter()
+        .map(|config| {
+            Ok(DaemonAccount {
+                config,
+                schedule: config.schedule()?,
+                state: saved_state.remove(config.name()).unwrap_or_default(),
+            })
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    for account in &mut accounts {
+        if account.state.failure_count >= MAX_FAILURES {
+            tracing::info!("{}: resuming paused account", account.config.name());
+            account.state.failure_count = 0;
+            account.state.next_attempt_at = None;
+        }
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let wakeup = Arc::new(Notify::new());
+    tokio::spawn({
+        let stop = stop.clone();
+        let wakeup = wakeup.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("received shutdown signal, finishing current list");
+            stop.store(true, Ordering::Relaxed);
+            wakeup.notify_one();
+        }
+    });
+
+    let options = RunOptions {
+        stop: Some(stop.clone()),
+        ..options
+    };
+
+    tracing::info!("daemon started with {} accounts", accounts.len());
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut success_count = 0;
+        let mut failure_count = 0;
+
+        for index in 0..accounts.len() {
+            if stop.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let account = &mut accounts[index];
+
+            let now = Utc::now().naive_utc();
+            if !account.is_due(now) {
+                continue;
+            }
+
+            let name = account.config.name();
+            let result = match account.config.token() {
+                Ok(token) => run_once(&account.config.host, &token, &options)
+                    .await
+                    .map_err(Error::from),
+                Err(e) => Err(e.into()),
+            };
+
+            match result {
+                Ok(stats) if stats.interrupted => {
+                    // we're about to exit, so this doesn't count as an attempt
+                    tracing::info!("{}: interrupted", name);
+                }
+                Ok(stats) => {
+                    tracing::info!("{}: synced {} lists", name, stats.list_count);
+                    account.state = AccountState {
+                        last_success_at: Some(now),
+                        next_attempt_at: Some(account.schedule.next_after(now)),
+                        failure_count: 0,
+                    };
+                    success_count += 1;
+                }
+                Err(e) => {
+                    account.state.failure_count += 1;
+                    account.state.next_attempt_at =
+                        Some(now + retry_delay(account.state.failure_count));
+                    failure_count += 1;
+
+                    if account.state.failure_count >= MAX_FAILURES {
+                        tracing::error!(
+                            "{}: failed {} times in a row, pausing until restart: {}",
+                            name,
+                            account.state.failure_count,
+                            e
+                        );
+                    } else {
+                        tracing::warn!("{}: failed to sync: {}", name, e);
+                    }
+                }
+            }
+
+            if let Err(e) = save_state(state_path, &accounts) {
+                tracing::error!("failed to save {}: {:#}", state_path.display(), e);
+            }
+        }
+
+        let paused = accounts
+            .iter()
+            .filter(|account| account.state.failure_count >= MAX_FAILURES)
+            .count();
+        let next = accounts
+            .iter()
+            .filter(|account| account.state.failure_count < MAX_FAILURES)
+            .filter_map(|account| account.state.next_attempt_at)
+            .min();
+        if success_count + failure_count > 0 {
+            tracing::info!(
+                "daemon cycle: {} success, {} failure, {} paused, next sync at {:?}",
+                success_count,
+                failure_count,
+                paused,
+                next
+            );
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = wakeup.notified() => {}
+        }
+    }
+
+    tracing::info!("daemon stopped");
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+#[test]
+fn state_roundtrip() {
+    let path = std::env::temp_dir()
+        .join(format!("list-bot-daemon-{}", std::process::id()))
+        .join("state.json");
+    assert!(load_state(&path).unwrap().is_empty());
+
+    let config = AccountConfig {
+        host: "example.com".to_owned(),
+        name: None,
+        token: Some("token".to_owned()),
+        token_file: None,
+        token_env: None,
+        schedule: None,
+        preferred_hour: None,
+    };
+    let next_attempt_at = Utc::now().naive_utc();
+    let account = DaemonAccount {
+        config: &config,
+        schedule: config.schedule().unwrap(),
+        state: AccountState {
+            last_success_at: None,
+            next_attempt_at: Some(next_attempt_at),
+            failure_count: 3,
+        },
+    };
+    save_state(&path, &[account]).unwrap();
+
+    let state = load_state(&path).unwrap();
+    assert_eq!(state["example.com"].failure_count, 3);
+    assert_eq!(state["example.com"].next_attempt_at, Some(next_attempt_at));
+
+    std::fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -5,6 +5,8 @@ use axum::{
 use reqwest::header::InvalidHeaderValue;
 use tokio::task::JoinError;
 
+use crate::crypto::CryptoError;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ResponseError {
     #[error("internal db error: {0}")]
@@ -21,14 +23,161 @@ pub enum ResponseError {
     Session(#[from] tower_sessions::session::Error),
     #[error("no login found")]
     NeedsAuth,
+    #[error("invalid input: {0}")]
+    BadRequest(String),
+    #[error("invalid or missing CSRF token, please reload the page and try again")]
+    InvalidCsrfToken,
+    #[error("{0}")]
+    Sync(#[from] SyncError),
     #[error("invalid base64")]
     Base64(#[from] data_encoding::DecodeError),
+    #[error("failed to encrypt token: {0}")]
+    Crypto(#[from] CryptoError),
+    #[error("failed to encode metrics: {0}")]
+    Metrics(#[from] prometheus::Error),
+}
+
+/// Why syncing an account failed. Persisted as `code()` plus the display message, so it can be
+/// rendered to the user without leaking backtraces.
+#[derive(Debug, thiserror::Error)]
+pub enum SyncError {
+    #[error("{0}")]
+    Auth(String),
+    #[error("{0}")]
+    RateLimited(String),
+    #[error("list {list_id} not found")]
+    ListNotFound { list_id: String },
+    #[error("account {acct} not found")]
+    AccountNotFound { acct: String },
+    #[error("{0}")]
+    InstanceUnreachable(String),
+    /// Gave up waiting for other syncs against the same instance.
+    #[error("{0}")]
+    HostBusy(String),
+    #[error("{0}")]
+    IncompatibleServer(String),
+    #[error("{0}")]
+    Parse(String),
+    #[error("{0}")]
+    SafetyLimit(String),
+    #[error("{0}")]
+    Internal(String),
+}
+
+impl SyncError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            SyncError::Auth(_) => "auth",
+            SyncError::RateLimited(_) => "rate_limited",
+            SyncError::ListNotFound { .. } => "list_not_found",
+            SyncError::AccountNotFound { .. } => "account_not_found",
+            SyncError::InstanceUnreachable(_) => "instance_unreachable",
+            SyncError::HostBusy(_) => "host_busy",
+            SyncError::IncompatibleServer(_) => "incompatible_server",
+            SyncError::Parse(_) => "parse",
+            SyncError::SafetyLimit(_) => "safety_limit",
+            SyncError::Internal(_) => "internal",
+        }
+    }
+
+    /// Classify a failed request. `context` describes what we were trying to do. A 404 is taken
+    /// to mean that the server lacks the endpoint, requests where it can mean something else
+    /// check for it first, see `SyncContext::list_context`.
+    pub fn from_reqwest(context: &str, e: reqwest::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+
+        if let Some(status) = e.status() {
+            return match status {
+                StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => SyncError::Auth(message),
+                StatusCode::TOO_MANY_REQUESTS => SyncError::RateLimited(message),
+                StatusCode::NOT_FOUND
+                | StatusCode::METHOD_NOT_ALLOWED
+                | StatusCode::GONE
+                | StatusCode::UNPROCESSABLE_ENTITY
+                | StatusCode::NOT_IMPLEMENTED => SyncError::IncompatibleServer(message),
+                status if status.is_server_error() => SyncError::InstanceUnreachable(message),
+                _ => SyncError::Internal(message),
+            };
+        }
+
+        if e.is_decode() {
+            SyncError::Parse(message)
+        } else if e.is_connect() || e.is_timeout() || e.is_request() {
+            SyncError::InstanceUnreachable(message)
+        } else {
+            SyncError::Internal(message)
+        }
+    }
+}
+
+impl From<ResponseError> for SyncError {
+    fn from(e: ResponseError) -> Self {
+        SyncError::Internal(e.to_string())
+    }
+}
+
+impl From<CryptoError> for SyncError {
+    fn from(e: CryptoError) -> Self {
+        SyncError::Internal(e.to_string())
+    }
+}
+
+/// A short explanation and a suggested action for a persisted `SyncError::code`.
+pub fn describe_sync_error(code: &str) -> (&'static str, &'static str) {
+    match code {
+        "auth" => (
+            "Your instance rejected our access token.",
+            "Log in again to re-authorize the bot.",
+        ),
+        "rate_limited" => (
+            "Your instance rate-limited us for too long.",
+            "Nothing to do, we will try again later.",
+        ),
+        "list_not_found" => (
+            "One of your lists disappeared while we were syncing it.",
+            "If you deleted or renamed a list, just sync again.",
+        ),
+        "account_not_found" => (
+            "An account we looked up does not exist on your instance.",
+            "Check the spelling of the account name.",
+        ),
+        "instance_unreachable" => (
+            "We could not reach your instance.",
+            "This is usually temporary, we will try again later.",
+        ),
+        "host_busy" => (
+            "Syncs of other accounts on your instance took too long.",
+            "Nothing to do, we will try again later.",
+        ),
+        "incompatible_server" => (
+            "Your instance does not support an API we need.",
+            "Check the README to see which server software is supported.",
+        ),
+        "parse" => (
+            "Your instance sent a response we did not understand.",
+            "Please open an issue on GitHub if this keeps happening.",
+        ),
+        "safety_limit" => (
+            "We stopped the sync because it would have removed an unusually large number of accounts.",
+            "Check that your list filters are correct. If the change is intended, click Sync anyway.",
+        ),
+        _ => (
+            "Something went wrong on our side.",
+            "Please open an issue on GitHub if this keeps happening.",
+        ),
+    }
 }
 
 impl IntoResponse for ResponseError {
     fn into_response(self) -> Response {
         match self {
             ResponseError::NeedsAuth => Redirect::to("/").into_response(),
+            ResponseError::BadRequest(_) => {
+                (StatusCode::BAD_REQUEST, format!("{}\n", self)).into_response()
+            }
+            ResponseError::InvalidCsrfToken => {
+                (StatusCode::FORBIDDEN, format!("{}\n", self)).into_response()
+            }
             _ => {
                 tracing::error!("error while serving request: {}", self);
                 (StatusCode::INTERNAL_SERVER_ERROR, format!("{}\n", self)).into_response()
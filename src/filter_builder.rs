@@ -0,0 +1,269 @@
+use axum::extract::State;
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::Form;
+use maud::Markup;
+use serde::Deserialize;
+
+use crate::api_cache::ApiCache;
+use crate::api_client::{ApiClient, SyncContext};
+use crate::api_models::List;
+use crate::auth::LoggedIn;
+use crate::csrf::CsrfToken;
+use crate::error::ResponseError;
+use crate::list_manager::ListManagerTerms;
+use crate::AppState;
+
+const PREVIEW_LIMIT: usize = 20;
+
+/// Form for composing a list title from the supported filter terms. Empty fields are ignored.
+#[derive(Deserialize, Default)]
+pub struct FilterForm {
+    #[serde(default)]
+    name: String,
+    mutuals: Option<String>,
+    #[serde(default)]
+    inactive_for: String,
+    #[serde(default)]
+    inactive_unit: String,
+    #[serde(default)]
+    active_within: String,
+    #[serde(default)]
+    active_unit: String,
+    /// ID of the list to rename, or empty to create a new list.
+    #[serde(default)]
+    list_id: String,
+}
+
+impl FilterForm {
+    /// Build the list title and check that all of it is understood by the parser used during sync.
+    fn to_title(&self) -> Result<(String, ListManagerTerms), String> {
+        let mut terms = Vec::new();
+
+        if !self.inactive_for.trim().is_empty() {
+            terms.push(format!(
+                "last_status_at>{}{}",
+                self.inactive_for.trim(),
+                self.inactive_unit
+            ));
+        }
+
+        if !self.active_within.trim().is_empty() {
+            terms.push(format!(
+                "last_status_at<{}{}",
+                self.active_within.trim(),
+                self.active_unit
+            ));
+        }
+
+        if self.mutuals.is_some() {
+            terms.push("mutuals".to_owned());
+        }
+
+        if terms.is_empty() {
+            return Err("select at least one filter".to_owned());
+        }
+
+        let name = self.name.trim();
+        if name.contains('#') {
+            return Err("the list name must not contain #".to_owned());
+        }
+
+        let title = format!("{} #{}", name, terms.join(" & ")).trim().to_owned();
+
+        // the sync's parser ignores anything after the last valid term, such as a bad unit
+        let parsed = ListManagerTerms::parse_exact(&title)
+            .map_err(|e| format!("invalid filter {:?}: {}", title, e))?;
+
+        Ok((title, parsed))
+    }
+}
+
+async fn get_client(state: &AppState, login: &LoggedIn) -> Result<ApiClient, ResponseError> {
+    let account = state.store.get_account(login.account()?).await?;
+    state.store.api_client(&account)
+}
+
+pub fn render_form(lists: &[List], csrf: &CsrfToken) -> Markup {
+    let unit_select = |name: &str| {
+        maud::html! {
+            select name=(name) {
+                option value="d" { "days" }
+                option value="w" { "weeks" }
+                option value="m" { "months" }
+            }
+        }
+    };
+
+    maud::html! {
+        form method="post" action="/account/filter-builder/save" {
+            (csrf.field())
+            label {
+                "List name"
+                input type="text" name="name" placeholder="e.g. Quiet friends";
+            }
+
+            fieldset {
+                label {
+                    input type="checkbox" name="mutuals";
+                    "Only mutuals"
+                }
+            }
+
+            label { "Last post more than ... ago" }
+            fieldset role="group" {
+                input type="number" min="1" name="inactive_for";
+                (unit_select("inactive_unit"))
+            }
+
+            label { "Last post less than ... ago" }
+            fieldset role="group" {
+                input type="number" min="1" name="active_within";
+                (unit_select("active_unit"))
+            }
+
+            label {
+                "Apply to"
+                select name="list_id" {
+                    option value="" { "A new list" }
+                    @for list in lists {
+                        option value=(list.id) { "Rename: "(list.title) }
+                    }
+                }
+            }
+
+            div.grid {
+                button.secondary
+                    type="button"
+                    data-hx-post="/account/filter-builder/preview"
+                    data-hx-target="#filter-preview"
+                    data-hx-swap="innerHTML" { "Preview" }
+                input type="submit" value="Save list";
+            }
+
+            div id="filter-preview" {}
+        }
+    }
+}
+
+pub async fn preview(
+    State(state): State<AppState>,
+    login: LoggedIn,
+    Form(form): Form<FilterForm>,
+) -> Result<Response, ResponseError> {
+    let (title, terms) = match form.to_title() {
+        Ok(result) => result,
+        Err(e) => {
+            return Ok(
+                Html(maud::html! { p."pico-color-red-500" { (e) } }.into_string()).into_response(),
+            )
+        }
+    };
+
+    let client = get_client(&state, &login).await?;
+    let mut api_cache = ApiCache::default();
+    let member_ids = terms.get_member_ids(&client, &mut api_cache).await?;
+    let members: Vec<_> = api_cache
+        .get_follows(&client)
+        .await?
+        .iter()
+        .filter(|account| member_ids.contains(&account.id))
+        .take(PREVIEW_LIMIT)
+        .map(|account| account.acct.clone())
+        .collect();
+
+    let html = maud::html! {
+        p {
+            "The list will be called " code { (title) } ", meaning "
+            (terms.describe())". "
+            (member_ids.len())" of the accounts you follow currently match."
+        }
+        @if !members.is_empty() {
+            ul {
+                @for acct in &members {
+                    li { "@"(acct) }
+                }
+                @if member_ids.len() > members.len() {
+                    li { "..." }
+                }
+            }
+        }
+    };
+
+    Ok(Html(html.into_string()).into_response())
+}
+
+pub async fn save(
+    State(state): State<AppState>,
+    login: LoggedIn,
+    Form(form): Form<FilterForm>,
+) -> Result<Response, ResponseError> {
+    let (title, _) = form.to_title().map_err(ResponseError::BadRequest)?;
+    // the ID ends up in the request path
+    if !form.list_id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(ResponseError::BadRequest("invalid list ID".to_owned()));
+    }
+    let client = get_client(&state, &login).await?;
+
+    let form_title = title.clone();
+    let builder_fn = Box::new(move |builder: reqwest::RequestBuilder| {
+        builder.form(&[("title", form_title.as_str())])
+    });
+
+    if form.list_id.is_empty() {
+        client
+            .post("/api/v1/lists", builder_fn)
+            .await
+            .sync_context("failed to create list")?
+            .error_for_status()
+            .sync_context("failed to create list")?;
+    } else {
+        client
+            .put(&format!("/api/v1/lists/{}", form.list_id), builder_fn)
+            .await
+            .list_context(&form.list_id, "failed to rename list")?
+            .error_for_status()
+            .list_context(&form.list_id, "failed to rename list")?;
+    }
+
+    tracing::info!("saved list {:?} via filter builder", title);
+    Ok(Redirect::to("/account/lists").into_response())
+}
+
+#[test]
+fn form_to_title() {
+    let form = FilterForm {
+        name: "Quiet friends".to_owned(),
+        mutuals: Some("on".to_owned()),
+        inactive_for: "3".to_owned(),
+        inactive_unit: "d".to_owned(),
+        ..FilterForm::default()
+    };
+    let (title, terms) = form.to_title().unwrap();
+    assert_eq!(title, "Quiet friends #last_status_at>3d & mutuals");
+    assert_eq!(terms.to_string(), "#last_status_at>3d & mutuals");
+
+    assert!(FilterForm::default().to_title().is_err());
+
+    let form = FilterForm {
+        active_within: "0".to_owned(),
+        active_unit: "d".to_owned(),
+        ..FilterForm::default()
+    };
+    assert!(form.to_title().is_err());
+
+    let form = FilterForm {
+        mutuals: Some("on".to_owned()),
+        active_within: "0".to_owned(),
+        active_unit: "d".to_owned(),
+        ..FilterForm::default()
+    };
+    assert!(form.to_title().is_err());
+
+    let form = FilterForm {
+        mutuals: Some("on".to_owned()),
+        inactive_for: "3".to_owned(),
+        inactive_unit: "dX".to_owned(),
+        ..FilterForm::default()
+    };
+    assert!(form.to_title().is_err());
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reqwest::Client;
+use tokio::sync::{Semaphore, SemaphorePermit};
+use tokio::time::Instant;
+
+use crate::api_client::{ApiClient, ClientConfig};
+use crate::error::ResponseError;
+
+/// State shared by all accounts on the same instance. Instances rate-limit by IP as well as by
+/// account, so accounts on one instance share a connection pool, back off together when any of
+/// them is rate-limited, and sync one at a time.
+pub struct HostState {
+    pub host: String,
+    /// `https://host`, or `http://host` if the host is in the plaintext allowlist.
+    pub base_url: String,
+    pub client: Client,
+    sync_slot: Semaphore,
+    paused_until: Mutex<Option<Instant>>,
+}
+
+impl HostState {
+    pub fn new(config: &ClientConfig, host: &str) -> Result<Self, ResponseError> {
+        Ok(HostState {
+            host: host.to_owned(),
+            base_url: config.base_url(host),
+            client: config.build_client()?,
+            sync_slot: Semaphore::new(1),
+            paused_until: Mutex::new(None),
+        })
+    }
+
+    /// Wait until no other sync is running against this instance. The slot is released when the
+    /// permit is dropped.
+    pub async fn sync_slot(&self) -> SemaphorePermit<'_> {
+        self.sync_slot
+            .acquire()
+            .await
+            .expect("sync slot semaphore is never closed")
+    }
+
+    /// Whether another sync is currently holding the slot.
+    pub fn is_busy(&self) -> bool {
+        self.sync_slot.available_permits() == 0
+    }
+
+    /// Stop all requests to this instance for `duration`, after one of them was rate-limited.
+    pub fn pause_for(&self, duration: Duration) {
+        let until = Instant::now() + duration;
+        let mut paused_until = self.paused_until.lock().unwrap();
+        if paused_until.is_none_or(|current| current < until) {
+            *paused_until = Some(until);
+        }
+    }
+
+    fn is_paused(&self) -> bool {
+        self.paused_until
+            .lock()
+            .unwrap()
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    /// Wait until a pause set by `pause_for` is over.
+    pub async fn wait_for_rate_limit(&self) {
+        let until = *self.paused_until.lock().unwrap();
+        if let Some(until) = until {
+            tokio::time::sleep_until(until).await;
+        }
+    }
+}
+
+/// Hands out one `HostState` per instance. States that nobody uses and that aren't paused are
+/// dropped when a new instance is added.
+#[derive(Clone, Default)]
+pub struct HostCoordinator {
+    config: Arc<ClientConfig>,
+    hosts: Arc<Mutex<HashMap<String, Arc<HostState>>>>,
+}
+
+impl HostCoordinator {
+    pub fn new(config: ClientConfig) -> Self {
+        HostCoordinator {
+            config: Arc::new(config),
+            hosts: Arc::default(),
+        }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.config
+    }
+
+    pub fn host(&self, host: &str) -> Result<Arc<HostState>, ResponseError> {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(state) = hosts.get(host) {
+            return Ok(state.clone());
+        }
+
+        hosts.retain(|_, state| Arc::strong_count(state) > 1 || state.is_paused());
+
+        let state = Arc::new(HostState::new(&self.config, host)?);
+        hosts.insert(host.to_owned(), state.clone());
+        Ok(state)
+    }
+
+    /// An API client that shares its connection pool and rate limit with other clients for the
+    /// same instance.
+    pub fn client(&self, host: &str, token: Option<&str>) -> Result<ApiClient, ResponseError> {
+        Ok(ApiClient::with_host(self.host(host)?, token))
+    }
+}
+
+#[tokio::test]
+async fn shared_host_state() {
+    let coordinator = HostCoordinator::default();
+    let a = coordinator.host("example.com").unwrap();
+    let b = coordinator.host("example.com").unwrap();
+    let c = coordinator.host("example.org").unwrap();
+    assert!(Arc::ptr_eq(&a, &b));
+    assert!(!Arc::ptr_eq(&a, &c));
+
+    let slot = a.sync_slot().await;
+    assert!(b.is_busy());
+    assert!(!c.is_busy());
+    drop(slot);
+    assert!(!b.is_busy());
+
+    // idle states are evicted, paused ones are kept
+    c.pause_for(Duration::from_secs(60));
+    drop((a, b, c));
+    coordinator.host("example.net").unwrap();
+    let hosts = coordinator.hosts.lock().unwrap();
+    assert!(!hosts.contains_key("example.com"));
+    assert!(hosts.contains_key("example.org"));
+}
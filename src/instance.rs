@@ -0,0 +1,355 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::Url;
+use url::Host;
+
+use crate::api_client::{ApiClient, SyncContext};
+use crate::api_models::{Instance, NodeInfo, NodeInfoLinks};
+use crate::error::ResponseError;
+use crate::hosts::HostCoordinator;
+
+/// Turn whatever the user typed into the login form into a bare hostname (plus port, if any).
+/// Accepts URLs, handles like `@user@host`, and internationalized domain names, which are
+/// converted to punycode.
+pub fn normalize_host(input: &str) -> Result<String, String> {
+    let mut host = input.trim();
+
+    for scheme in ["https://", "http://"] {
+        if host.len() >= scheme.len() && host[..scheme.len()].eq_ignore_ascii_case(scheme) {
+            host = &host[scheme.len()..];
+        }
+    }
+
+    host = host.split(['/', '?', '#']).next().unwrap_or_default();
+
+    // @user@host or user@host
+    if let Some((_, domain)) = host.rsplit_once('@') {
+        host = domain;
+    }
+
+    if host.is_empty() {
+        return Err("please enter the domain of your instance, e.g. mastodon.social".to_owned());
+    }
+
+    let url = Url::parse(&format!("https://{}/", host))
+        .map_err(|e| format!("{:?} is not a valid domain: {}", host, e))?;
+    let domain = url
+        .host_str()
+        .ok_or_else(|| format!("{:?} is not a valid domain", host))?;
+
+    Ok(match url.port() {
+        Some(port) => format!("{}:{}", domain, port),
+        None => domain.to_owned(),
+    })
+}
+
+/// Whether an address is reachable on the public internet, as opposed to loopback, private
+/// networks and the like.
+pub fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // "this network", 0.0.0.0/8
+                || a == 0
+                // reserved, 240.0.0.0/4
+                || a >= 240
+                // shared address space, RFC 6598
+                || (a == 100 && (64..128).contains(&b))
+                // benchmarking, 198.18.0.0/15
+                || (a == 198 && (18..20).contains(&b))
+                // IETF protocol assignments, 192.0.0.0/24
+                || (a == 192 && b == 0 && c == 0))
+        }
+        IpAddr::V6(ip) => {
+            // IPv4-mapped ::ffff:a.b.c.d and IPv4-compatible ::a.b.c.d, which also covers :: and
+            // ::1 as 0.0.0.0 and 0.0.0.1
+            if let Some(ip) = ip.to_ipv4() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+            let [first, second, third, ..] = ip.segments();
+            // 6to4, 2002::/16, routed to the IPv4 address in the next 32 bits
+            if first == 0x2002 {
+                let ipv4 = (u32::from(second) << 16) | u32::from(third);
+                return is_public_ip(IpAddr::V4(ipv4.into()));
+            }
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                // unique local, fc00::/7
+                || (first & 0xfe00) == 0xfc00
+                // link local, fe80::/10
+                || (first & 0xffc0) == 0xfe80
+                // NAT64, 64:ff9b::/96 and 64:ff9b:1::/48, which can reach IPv4 private networks
+                || (first == 0x64 && (second == 0xff9b || second == 0xff9b + 1))
+                // documentation, 2001:db8::/32
+                || (first == 0x2001 && second == 0xdb8))
+        }
+    }
+}
+
+/// Resolve the host and reject it if any of its addresses are not public, so that the server
+/// can't be used to make requests into the network it is running in.
+pub async fn check_public(host: &str) -> Result<(), ResponseError> {
+    let url = Url::parse(&format!("https://{}/", host))
+        .map_err(|e| ResponseError::BadRequest(format!("invalid host {:?}: {}", host, e)))?;
+    let port = url.port_or_known_default().unwrap_or(443);
+
+    let addrs: Vec<IpAddr> = match url.host() {
+        Some(Host::Ipv4(ip)) => vec![IpAddr::V4(ip)],
+        Some(Host::Ipv6(ip)) => vec![IpAddr::V6(ip)],
+        Some(Host::Domain(domain)) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| ResponseError::BadRequest(format!("could not resolve {}: {}", host, e)))?
+            .map(|addr| addr.ip())
+            .collect(),
+        None => Vec::new(),
+    };
+
+    if addrs.is_empty() {
+        return Err(ResponseError::BadRequest(format!(
+            "could not resolve {}",
+            host
+        )));
+    }
+
+    if addrs.iter().any(|ip| !is_public_ip(*ip)) {
+        return Err(ResponseError::BadRequest(format!(
+            "{} points to a private or local address",
+            host
+        )));
+    }
+
+    Ok(())
+}
+
+/// DNS resolver for the HTTP client that drops addresses that are not public. `check_public` only
+/// runs at login, this also covers DNS changes after that and redirects to other domains.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| is_public_ip(addr.ip()))
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public addresses", host).into());
+            }
+
+            let addrs: Addrs = Box::new(addrs.into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstanceSoftware {
+    Mastodon,
+    GoToSocial,
+    Pleroma,
+    Akkoma,
+    /// Named in nodeinfo, but not one we know.
+    Other(String),
+    /// No nodeinfo, but speaks the Mastodon API.
+    Unknown,
+}
+
+impl fmt::Display for InstanceSoftware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InstanceSoftware::Mastodon => write!(f, "Mastodon"),
+            InstanceSoftware::GoToSocial => write!(f, "GoToSocial"),
+            InstanceSoftware::Pleroma => write!(f, "Pleroma"),
+            InstanceSoftware::Akkoma => write!(f, "Akkoma"),
+            InstanceSoftware::Other(name) => write!(f, "{}", name),
+            InstanceSoftware::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl InstanceSoftware {
+    fn from_nodeinfo_name(name: &str) -> Self {
+        match name.to_lowercase().as_str() {
+            "mastodon" => InstanceSoftware::Mastodon,
+            "gotosocial" => InstanceSoftware::GoToSocial,
+            "pleroma" => InstanceSoftware::Pleroma,
+            "akkoma" => InstanceSoftware::Akkoma,
+            _ => InstanceSoftware::Other(name.to_owned()),
+        }
+    }
+
+    /// Things the user should know before logging in with this server software.
+    pub fn warnings(&self) -> Vec<String> {
+        match self {
+            InstanceSoftware::Mastodon => Vec::new(),
+            InstanceSoftware::Other(name) => vec![format!(
+                "We don't know the server software {:?}. Syncing only works if it implements the Mastodon API for lists and relationships.",
+                name
+            )],
+            InstanceSoftware::Unknown => vec![
+                "We could not detect the server software. Syncing only works if it implements the Mastodon API for lists and relationships.".to_owned(),
+            ],
+            _ => vec![format!(
+                "{} implements most of the Mastodon API, but list syncing is mostly tested against Mastodon. Some filters may not work as expected.",
+                self
+            )],
+        }
+    }
+}
+
+/// Find out what software the instance runs, via nodeinfo, falling back to the Mastodon instance
+/// API. Fails if the host doesn't look like a fediverse server at all.
+pub async fn detect_software(
+    hosts: &HostCoordinator,
+    host: &str,
+) -> Result<InstanceSoftware, ResponseError> {
+    let client = hosts.client(host, None)?;
+
+    match get_nodeinfo(&client).await {
+        Ok(nodeinfo) => {
+            tracing::debug!(
+                "{} runs {} {}",
+                host,
+                nodeinfo.software.name,
+                nodeinfo.software.version
+            );
+            return Ok(InstanceSoftware::from_nodeinfo_name(
+                &nodeinfo.software.name,
+            ));
+        }
+        Err(e) => tracing::debug!("no nodeinfo for {}: {}", host, e),
+    }
+
+    let instance: Instance = client
+        .get("/api/v2/instance", Box::new(|builder| builder))
+        .await
+        .and_then(|res| res.error_for_status())
+        .map_err(|e| {
+            ResponseError::BadRequest(format!(
+                "{} does not look like a Mastodon-compatible server: {}",
+                host, e
+            ))
+        })?
+        .json()
+        .await?;
+
+    tracing::debug!(
+        "{} runs unknown software, version {}",
+        host,
+        instance.version
+    );
+    Ok(InstanceSoftware::Unknown)
+}
+
+async fn get_nodeinfo(client: &ApiClient) -> Result<NodeInfo, ResponseError> {
+    let links: NodeInfoLinks = client
+        .get("/.well-known/nodeinfo", Box::new(|builder| builder))
+        .await
+        .sync_context("failed to fetch nodeinfo links")?
+        .error_for_status()
+        .sync_context("failed to fetch nodeinfo links")?
+        .json()
+        .await
+        .sync_context("failed to parse nodeinfo links")?;
+
+    let link = links
+        .links
+        .iter()
+        .find(|link| {
+            link.rel
+                .starts_with("http://nodeinfo.diaspora.software/ns/schema/")
+        })
+        .ok_or_else(|| ResponseError::BadRequest("no nodeinfo schema found".to_owned()))?;
+
+    // only follow links to the same host, we only checked that one for being public
+    if normalize_host(&link.href).as_ref() != Ok(&client.host) {
+        return Err(ResponseError::BadRequest(format!(
+            "nodeinfo points to another host: {}",
+            link.href
+        )));
+    }
+
+    let nodeinfo = client
+        .get(&link.href, Box::new(|builder| builder))
+        .await
+        .sync_context("failed to fetch nodeinfo")?
+        .error_for_status()
+        .sync_context("failed to fetch nodeinfo")?
+        .json()
+        .await
+        .sync_context("failed to parse nodeinfo")?;
+
+    Ok(nodeinfo)
+}
+
+#[test]
+fn normalize() {
+    for input in [
+        "mastodon.social",
+        " Mastodon.Social ",
+        "https://mastodon.social/",
+        "HTTP://mastodon.social/@user",
+        "@user@mastodon.social",
+        "user@mastodon.social",
+    ] {
+        assert_eq!(
+            normalize_host(input).unwrap(),
+            "mastodon.social",
+            "{}",
+            input
+        );
+    }
+
+    assert_eq!(
+        normalize_host("bücher.example").unwrap(),
+        "xn--bcher-kva.example"
+    );
+    assert_eq!(normalize_host("localhost:3000").unwrap(), "localhost:3000");
+    assert!(normalize_host("").is_err());
+    assert!(normalize_host("https://").is_err());
+    assert!(normalize_host("exa mple.com").is_err());
+}
+
+#[test]
+fn public_ips() {
+    for ip in ["1.1.1.1", "2a00:1450:4001::1", "2002:101:101::1"] {
+        assert!(is_public_ip(ip.parse().unwrap()), "{}", ip);
+    }
+
+    for ip in [
+        "127.0.0.1",
+        "10.1.2.3",
+        "192.168.0.1",
+        "169.254.169.254",
+        "100.64.0.1",
+        "0.0.0.0",
+        "0.1.2.3",
+        "240.0.0.1",
+        "224.0.0.1",
+        "198.18.0.1",
+        "::1",
+        "ff02::1",
+        "64:ff9b::a00:1",
+        "fd00::1",
+        "fe80::1",
+        "::ffff:127.0.0.1",
+        "::127.0.0.1",
+        "::10.1.2.3",
+        "2002:7f00:1::1",
+        "2002:a9fe:a9fe::",
+    ] {
+        assert!(!is_public_ip(ip.parse().unwrap()), "{}", ip);
+    }
+}
@@ -1,105 +1,148 @@
 use std::collections::BTreeSet;
+use std::fmt;
 use std::str::FromStr;
 
-use anyhow::{Context, Error};
-use chrono::{Days, Local};
+use chrono::{Days, Local, NaiveDate, NaiveDateTime, Utc};
 use itertools::Itertools;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize, Serializer};
 
-use crate::{api_cache::ApiCache, api_client::ApiClient, api_helpers, api_models};
+use crate::api_cache::ApiCache;
+use crate::api_client::{ApiClient, SyncContext};
+use crate::error::SyncError;
+use crate::progress::SyncProgress;
+use crate::safety::SafetyLimits;
+use crate::snapshot::{ListRecord, SnapshotWriter};
+use crate::{api_helpers, api_models};
 
 const UPDATE_CHUNK_SIZE: usize = 250;
-
-#[derive(Debug, Clone, Eq, PartialEq)]
-enum ListManagerTerm {
-    LastStatus { is_gt: bool, days: Days },
+/// In incremental mode, the members of a list are fetched again after this long even if the list
+/// needs no changes, to pick up changes made outside of the bot.
+const RECORD_MAX_AGE: chrono::Duration = chrono::Duration::days(7);
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum ListManagerTerm {
+    LastStatus {
+        is_gt: bool,
+        #[serde(serialize_with = "days_serialize")]
+        days: Days,
+    },
     Mutuals,
 }
 
-#[derive(Debug, Clone, Eq, PartialEq)]
-struct ListManagerTerms(Vec<ListManagerTerm>); // and
-
-impl FromStr for ListManagerTerms {
-    type Err = pom::Error;
+impl ListManagerTerm {
+    pub fn describe(&self) -> String {
+        match self {
+            ListManagerTerm::LastStatus { is_gt: true, days } => {
+                format!("last post more than {} days ago", num_days(*days))
+            }
+            ListManagerTerm::LastStatus { is_gt: false, days } => {
+                format!("last post less than {} days ago", num_days(*days))
+            }
+            ListManagerTerm::Mutuals => "mutuals".to_owned(),
+        }
+    }
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        use pom::parser::*;
+impl fmt::Display for ListManagerTerm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListManagerTerm::LastStatus { is_gt, days } => {
+                write!(
+                    f,
+                    "last_status_at{}{}d",
+                    if *is_gt { '>' } else { '<' },
+                    num_days(*days)
+                )
+            }
+            ListManagerTerm::Mutuals => write!(f, "mutuals"),
+        }
+    }
+}
 
-        let whitespace = || sym(b' ').repeat(0..).discard();
-        let duration_days =
-            (one_of(b"123456789").repeat(1..3) + one_of(b"dwm")).map(|(number, unit)| {
-                let unit = match unit {
-                    b'd' => 1,
-                    b'w' => 7,
-                    b'm' => 30,
-                    _ => unreachable!(),
-                };
-
-                let number = String::from_utf8(number).unwrap().parse::<u64>().unwrap();
-                Days::new(number * unit)
-            });
+/// Terms combined with AND. `Display` produces the normalized form of the filter, e.g.
+/// `#last_status_at>7d & mutuals`.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ListManagerTerms(pub Vec<ListManagerTerm>);
 
-        let last_status_at = seq(b"last_status_at")
-            * whitespace()
-            * (one_of(b"<>") + whitespace() * duration_days).map(|(op, days)| {
-                ListManagerTerm::LastStatus {
-                    is_gt: op == b'>',
-                    days,
-                }
-            });
-        let mutuals = seq(b"mutuals").map(|_| ListManagerTerm::Mutuals);
-        let term = last_status_at | mutuals;
-        let and_symbol = whitespace() * sym(b'&').repeat(1..) * whitespace();
-        let and_term = list(term, and_symbol).convert(|terms| match terms.len() {
-            0 => Err("empty filter"),
-            _ => Ok(ListManagerTerms(terms)),
-        });
-        let complex_term = and_term;
-        let parser = none_of(b"#").repeat(0..).discard() * sym(b'#') * complex_term;
-        parser.parse(s.as_bytes())
+impl ListManagerTerms {
+    /// Human-readable form, e.g. "mutuals AND last post more than 3 days ago".
+    pub fn describe(&self) -> String {
+        self.0.iter().map(ListManagerTerm::describe).join(" AND ")
     }
-}
 
-pub struct ListManager {
-    list: api_models::List,
-    terms: ListManagerTerms,
-}
+    /// Parse the filter out of a list title. Returns `None` if the title has no filter at all,
+    /// i.e. the list is not supposed to be managed by us.
+    pub fn from_title(title: &str) -> Option<Result<Self, String>> {
+        if !title.contains('#') {
+            return None;
+        }
 
-impl ListManager {
-    fn new(list: api_models::List, terms: ListManagerTerms) -> Self {
-        ListManager { list, terms }
+        Some(title.parse().map_err(|e: pom::Error| e.to_string()))
     }
 
-    pub fn parse(list: api_models::List) -> Option<Self> {
-        let parsed = list.title.parse().ok()?;
-        Some(Self::new(list, parsed))
+    /// Stricter than `from_str`, which ignores anything after the last term it understands: the
+    /// whole title after the `#` has to be a valid filter. The error points at the first byte
+    /// that doesn't fit.
+    pub fn parse_exact(title: &str) -> Result<Self, FilterError> {
+        let Some(hash) = title.find('#') else {
+            return Err(FilterError {
+                position: title.len(),
+                message: "expected a # followed by the filter".to_owned(),
+            });
+        };
+
+        let input = title.as_bytes();
+        let mut terms = Vec::new();
+        let mut pos = hash + 1;
+
+        loop {
+            let (term, next) = term()
+                .parse_at(input, pos)
+                .map_err(|e| FilterError::from_pom(e, pos))?;
+            terms.push(term);
+            pos = next;
+
+            match and_symbol().parse_at(input, pos) {
+                Ok((_, next)) => pos = next,
+                Err(_) => break,
+            }
+        }
+
+        let (_, end) = whitespace()
+            .parse_at(input, pos)
+            .map_err(|e| FilterError::from_pom(e, pos))?;
+        if end < input.len() {
+            return Err(FilterError {
+                position: end,
+                message: "expected & or the end of the filter".to_owned(),
+            });
+        }
+
+        Ok(ListManagerTerms(terms))
     }
 
-    async fn get_new_member_ids(
-        &mut self,
+    /// Evaluate the filter against the user's follows, returning the matching account IDs.
+    pub async fn get_member_ids(
+        &self,
         client: &ApiClient,
         api_cache: &mut ApiCache,
-    ) -> Result<BTreeSet<String>, Error> {
+    ) -> Result<BTreeSet<String>, SyncError> {
         let mut first = true;
         let mut result = BTreeSet::new();
 
-        for term in &self.terms.0 {
+        for term in &self.0 {
             let term_result = match term {
                 ListManagerTerm::LastStatus { is_gt, days } => {
-                    let is_gt = *is_gt;
-                    let cutoff = Local::now().date_naive() - *days;
+                    let cutoff = last_status_cutoff(*days);
                     api_cache
                         .get_follows(client)
                         .await?
                         .iter()
                         .filter(|account| {
-                            let Some(last_status_at) = account.last_status_at else {
-                                return is_gt;
-                            };
-                            if is_gt {
-                                last_status_at < cutoff
-                            } else {
-                                last_status_at > cutoff
-                            }
+                            last_status_matches(*is_gt, cutoff, account.last_status_at)
                         })
                         .map(|account| account.id.clone())
                         .collect()
@@ -111,7 +154,7 @@ impl ListManager {
                         .get_relationships(client, follow_ids)
                         .await?
                         .into_iter()
-                        .filter(|relationship| relationship.following && relationship.followed_by)
+                        .filter(is_mutual)
                         .map(|relationship| relationship.id)
                         .collect()
                 }
@@ -128,112 +171,531 @@ impl ListManager {
         Ok(result)
     }
 
-    pub async fn sync_list(
-        &mut self,
+    /// Like `get_member_ids`, but for a single account, and with the reasoning for each term.
+    /// Only accounts the user follows can match.
+    pub async fn explain(
+        &self,
         client: &ApiClient,
         api_cache: &mut ApiCache,
-    ) -> Result<(), Error> {
-        tracing::info!("syncing list {} ({})", self.list.id, self.list.title);
+        account_id: &str,
+    ) -> Result<Option<Vec<TermVerdict>>, SyncError> {
+        let Some(account) = api_cache
+            .get_follows(client)
+            .await?
+            .iter()
+            .find(|account| account.id == account_id)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+
+        let mut verdicts = Vec::new();
+
+        for term in &self.0 {
+            let (matches, detail) = match term {
+                ListManagerTerm::LastStatus { is_gt, days } => {
+                    let cutoff = last_status_cutoff(*days);
+                    let matches = last_status_matches(*is_gt, cutoff, account.last_status_at);
+                    let last_status_at = match account.last_status_at {
+                        Some(date) => date.to_string(),
+                        None => "never".to_owned(),
+                    };
+                    let detail = format!(
+                        "last post: {}, cutoff: {}, must be {} the cutoff",
+                        last_status_at,
+                        cutoff,
+                        if *is_gt { "before" } else { "after" }
+                    );
+                    (matches, detail)
+                }
+                ListManagerTerm::Mutuals => {
+                    let ids = BTreeSet::from([account.id.clone()]);
+                    let relationship = api_cache.get_relationships(client, ids).await?.pop();
+                    match relationship {
+                        Some(relationship) => (
+                            is_mutual(&relationship),
+                            format!(
+                                "you follow them: {}, they follow you: {}",
+                                relationship.following, relationship.followed_by
+                            ),
+                        ),
+                        None => (false, "no relationship returned".to_owned()),
+                    }
+                }
+            };
 
-        let mut new_member_ids = self.get_new_member_ids(client, api_cache).await?;
+            verdicts.push(TermVerdict {
+                term: term.clone(),
+                matches,
+                detail,
+            });
+        }
 
-        let mut url_opt = Some(format!("/api/v1/lists/{}/accounts", self.list.id));
+        Ok(Some(verdicts))
+    }
+}
 
-        let mut to_delete = Vec::new();
+/// Outcome of evaluating one term for one account, see `ListManagerTerms::explain`.
+#[derive(Debug, Clone)]
+pub struct TermVerdict {
+    pub term: ListManagerTerm,
+    pub matches: bool,
+    pub detail: String,
+}
 
-        let mut num_old_accounts = 0usize;
-        let num_new_accounts = new_member_ids.len();
+fn last_status_cutoff(days: Days) -> NaiveDate {
+    Local::now().date_naive() - days
+}
 
-        while let Some(url) = url_opt.clone() {
-            let res = client
-                .get(&url, Box::new(|builder| builder))
-                .await?
-                .error_for_status()?;
+/// `Days` has no accessor for its value, so measure it against a fixed date.
+fn num_days(days: Days) -> u64 {
+    let date = NaiveDate::MAX;
+    (date - (date - days)).num_days() as u64
+}
 
-            let next_url = api_helpers::get_next_link(&res);
-            let accounts: Vec<api_models::Account> = res.json().await?;
+fn days_serialize<S: Serializer>(days: &Days, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(num_days(*days))
+}
 
-            for account in accounts {
-                num_old_accounts += 1;
-                // we have ensured that account is in the list, cross it off
-                let was_present = new_member_ids.remove(&account.id);
+/// Accounts that never posted count as inactive.
+fn last_status_matches(is_gt: bool, cutoff: NaiveDate, last_status_at: Option<NaiveDate>) -> bool {
+    let Some(last_status_at) = last_status_at else {
+        return is_gt;
+    };
+    if is_gt {
+        last_status_at < cutoff
+    } else {
+        last_status_at > cutoff
+    }
+}
 
-                // if it turns out that new_member_ids didn't contain the account, the account
-                // isn't supposed to be on the list. enqueue it for deletion.
-                if !was_present {
-                    to_delete.push(account.id);
-                }
-            }
+fn is_mutual(relationship: &api_models::Relationship) -> bool {
+    relationship.following && relationship.followed_by
+}
+
+impl fmt::Display for ListManagerTerms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0.iter().join(" & "))
+    }
+}
+
+impl FromStr for ListManagerTerms {
+    type Err = pom::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use pom::parser::*;
 
-            url_opt = next_url;
+        let and_term = list(term(), and_symbol()).convert(|terms| match terms.len() {
+            0 => Err("empty filter"),
+            _ => Ok(ListManagerTerms(terms)),
+        });
+        let complex_term = and_term;
+        let parser = none_of(b"#").repeat(0..).discard() * sym(b'#') * complex_term;
+        parser.parse(s.as_bytes())
+    }
+}
+
+/// Why `ListManagerTerms::parse_exact` failed, and at which byte offset of the title.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+#[error("{message} at position {position}")]
+pub struct FilterError {
+    pub position: usize,
+    pub message: String,
+}
+
+impl FilterError {
+    fn from_pom(e: pom::Error, start: usize) -> Self {
+        match e {
+            pom::Error::Expect {
+                message, position, ..
+            } => FilterError {
+                position,
+                message: message.replacen("Expect", "expected", 1),
+            },
+            _ => FilterError {
+                position: start,
+                message: "expected mutuals or last_status_at".to_owned(),
+            },
         }
+    }
+}
 
-        for account_chunk in new_member_ids
-            .into_iter()
-            .collect_vec()
-            .chunks(UPDATE_CHUNK_SIZE)
-        {
-            let account_ids = account_chunk.to_vec();
-            tracing::debug!(
-                "syncing list {} ({}): adding accounts: {:?}",
-                self.list.id,
-                self.list.title,
-                account_ids
-            );
-
-            client
-                .post(
-                    &format!("/api/v1/lists/{}/accounts", self.list.id),
-                    Box::new(move |builder| {
-                        builder.form(
-                            &account_ids
-                                .iter()
-                                .map(|id| ("account_ids[]", id))
-                                .collect_vec(),
-                        )
-                    }),
-                )
-                .await
-                .context("failed to add members")?
-                .error_for_status()
-                .context("failed to add members")?;
+fn whitespace<'a>() -> pom::parser::Parser<'a, u8, ()> {
+    pom::parser::sym(b' ').repeat(0..).discard()
+}
+
+fn and_symbol<'a>() -> pom::parser::Parser<'a, u8, ()> {
+    whitespace() * pom::parser::sym(b'&').repeat(1..).discard() * whitespace()
+}
+
+fn term<'a>() -> pom::parser::Parser<'a, u8, ListManagerTerm> {
+    use pom::parser::*;
+
+    let duration_days =
+        (one_of(b"123456789").repeat(1..3) + one_of(b"dwm")).map(|(number, unit)| {
+            let unit = match unit {
+                b'd' => 1,
+                b'w' => 7,
+                b'm' => 30,
+                _ => unreachable!(),
+            };
+
+            let number = String::from_utf8(number).unwrap().parse::<u64>().unwrap();
+            Days::new(number * unit)
+        });
+
+    let last_status_at = seq(b"last_status_at")
+        * whitespace()
+        * (one_of(b"<>").expect("< or >")
+            + whitespace() * duration_days.expect("a duration like 7d, 2w or 3m"))
+        .map(|(op, days)| ListManagerTerm::LastStatus {
+            is_gt: op == b'>',
+            days,
+        });
+    let mutuals = seq(b"mutuals").map(|_| ListManagerTerm::Mutuals);
+    last_status_at | mutuals
+}
+
+/// What happened to a list during sync.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListSyncStats {
+    pub list_id: String,
+    pub title: String,
+    /// Normalized filter expression.
+    pub filter: String,
+    pub member_count_before: usize,
+    pub member_count: usize,
+    pub added: usize,
+    pub removed: usize,
+    pub added_accounts: Vec<ListMember>,
+    pub removed_accounts: Vec<ListMember>,
+    /// Whether the previous members were taken from the record of the last sync instead of
+    /// being fetched.
+    pub incremental: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListMember {
+    pub id: String,
+    pub acct: String,
+}
+
+pub struct ListManager {
+    list: api_models::List,
+    terms: ListManagerTerms,
+}
+
+impl ListManager {
+    fn new(list: api_models::List, terms: ListManagerTerms) -> Self {
+        ListManager { list, terms }
+    }
+
+    pub fn parse(list: api_models::List) -> Option<Self> {
+        let parsed = list.title.parse().ok()?;
+        Some(Self::new(list, parsed))
+    }
+
+    pub fn list_id(&self) -> &str {
+        &self.list.id
+    }
+
+    pub fn title(&self) -> &str {
+        &self.list.title
+    }
+
+    async fn get_new_member_ids(
+        &mut self,
+        client: &ApiClient,
+        api_cache: &mut ApiCache,
+    ) -> Result<BTreeSet<String>, SyncError> {
+        self.terms.get_member_ids(client, api_cache).await
+    }
+
+    pub async fn sync_list(
+        &mut self,
+        client: &ApiClient,
+        api_cache: &mut ApiCache,
+        snapshots: &SnapshotWriter,
+        options: &ReplaceOptions<'_>,
+    ) -> Result<ListSyncStats, SyncError> {
+        tracing::info!("syncing list {} ({})", self.list.id, self.list.title);
+
+        let new_member_ids = self.get_new_member_ids(client, api_cache).await?;
+        let members = api_cache
+            .get_follows(client)
+            .await?
+            .iter()
+            .filter(|account| new_member_ids.contains(&account.id))
+            .map(|account| ListMember {
+                id: account.id.clone(),
+                acct: account.acct.clone(),
+            })
+            .collect();
+        let change = replace_members(client, &self.list, members, snapshots, options).await?;
+
+        client.progress.report(SyncProgress::ListDone {
+            title: self.list.title.clone(),
+            added: change.added.len(),
+            removed: change.removed.len(),
+        });
+
+        Ok(ListSyncStats {
+            list_id: self.list.id.clone(),
+            title: self.list.title.clone(),
+            filter: self.terms.to_string(),
+            member_count_before: change.member_count_before,
+            member_count: change.member_count,
+            added: change.added.len(),
+            removed: change.removed.len(),
+            added_accounts: change.added,
+            removed_accounts: change.removed,
+            incremental: change.incremental,
+        })
+    }
+}
+
+/// How `replace_members` may change the list.
+pub struct ReplaceOptions<'a> {
+    pub limits: &'a SafetyLimits,
+    /// Diff against the members recorded at the last sync instead of fetching them, see
+    /// `ListRecord`.
+    pub incremental: bool,
+}
+
+/// Result of `replace_members`.
+pub struct MembershipChange {
+    pub member_count_before: usize,
+    pub member_count: usize,
+    pub added: Vec<ListMember>,
+    pub removed: Vec<ListMember>,
+    /// Whether the previous members were taken from the record.
+    pub incremental: bool,
+}
+
+/// Make the list contain exactly `members`, adding and removing accounts in chunks. If that
+/// changes anything, the previous members are fetched and snapshotted first. Nothing is changed
+/// if it would remove more members than the limits allow. Afterwards, the new members are
+/// recorded for incremental syncs.
+pub async fn replace_members(
+    client: &ApiClient,
+    list: &api_models::List,
+    members: Vec<ListMember>,
+    snapshots: &SnapshotWriter,
+    options: &ReplaceOptions<'_>,
+) -> Result<MembershipChange, SyncError> {
+    let now = Utc::now().naive_utc();
+
+    if options.incremental {
+        let record = snapshots.load_record(&client.host, &list.id).await?;
+        match record {
+            Some(record) if record.list_title != list.title => {
+                tracing::info!("list {} was renamed, fetching its members", list.id);
+            }
+            Some(record) if now - record.verified_at > RECORD_MAX_AGE => {
+                tracing::info!("record of list {} is old, fetching its members", list.id);
+            }
+            // the record is only trusted to skip a list that needs no changes. snapshots and
+            // diffs are always based on the real members, which may have been edited by hand.
+            Some(record) if same_members(&members, &record.members) => {
+                tracing::debug!("list {} is unchanged since the last sync", list.id);
+                return Ok(MembershipChange {
+                    member_count_before: members.len(),
+                    member_count: members.len(),
+                    added: Vec::new(),
+                    removed: Vec::new(),
+                    incremental: true,
+                });
+            }
+            Some(_) => {
+                tracing::info!("list {} needs changes, fetching its members", list.id);
+            }
+            None => {}
         }
+    }
 
-        for account_chunk in to_delete.chunks(UPDATE_CHUNK_SIZE) {
-            let account_ids = account_chunk.to_vec();
-            tracing::debug!(
-                "syncing list {} ({}): deleting accounts: {:?}",
-                self.list.id,
-                self.list.title,
-                account_ids
-            );
-
-            client
-                .delete(
-                    &format!("/api/v1/lists/{}/accounts", self.list.id),
-                    Box::new(move |builder| {
-                        builder.form(
-                            &account_ids
-                                .iter()
-                                .map(|id| ("account_ids[]", id))
-                                .collect_vec(),
-                        )
-                    }),
-                )
-                .await
-                .context("failed to remove members")?
-                .error_for_status()
-                .context("failed to remove members")?;
+    let old_members = get_list_members(client, list).await?;
+    apply_members(
+        client,
+        list,
+        &members,
+        old_members,
+        now,
+        snapshots,
+        options.limits,
+    )
+    .await
+}
+
+fn same_members(a: &[ListMember], b: &[ListMember]) -> bool {
+    let ids = |members: &[ListMember]| -> BTreeSet<String> {
+        members.iter().map(|member| member.id.clone()).collect()
+    };
+    ids(a) == ids(b)
+}
+
+async fn get_list_members(
+    client: &ApiClient,
+    list: &api_models::List,
+) -> Result<Vec<ListMember>, SyncError> {
+    let mut url_opt = Some(format!("/api/v1/lists/{}/accounts", list.id));
+    let mut members = Vec::new();
+
+    while let Some(url) = url_opt.clone() {
+        let res = client
+            .get(&url, Box::new(|builder| builder))
+            .await
+            .sync_context("failed to get list members")?;
+
+        if res.status() == StatusCode::NOT_FOUND {
+            return Err(SyncError::ListNotFound {
+                list_id: list.id.clone(),
+            });
         }
 
-        tracing::info!(
-            "done syncing, went from {} to {} members",
-            num_old_accounts,
-            num_new_accounts
+        let res = res
+            .error_for_status()
+            .sync_context("failed to get list members")?;
+
+        let next_url = api_helpers::get_next_link(&res);
+        let accounts: Vec<api_models::Account> = res
+            .json()
+            .await
+            .sync_context("failed to parse list members")?;
+
+        members.extend(accounts.into_iter().map(|account| ListMember {
+            id: account.id,
+            acct: account.acct,
+        }));
+
+        url_opt = next_url;
+    }
+
+    Ok(members)
+}
+
+/// Diff `old_members` against `members` and apply the difference.
+async fn apply_members(
+    client: &ApiClient,
+    list: &api_models::List,
+    members: &[ListMember],
+    old_members: Vec<ListMember>,
+    verified_at: NaiveDateTime,
+    snapshots: &SnapshotWriter,
+    limits: &SafetyLimits,
+) -> Result<MembershipChange, SyncError> {
+    let member_ids: BTreeSet<&str> = members.iter().map(|member| member.id.as_str()).collect();
+    let old_member_ids: BTreeSet<&str> = old_members
+        .iter()
+        .map(|member| member.id.as_str())
+        .collect();
+
+    let to_add = members
+        .iter()
+        .filter(|member| !old_member_ids.contains(member.id.as_str()))
+        .cloned()
+        .collect_vec();
+    let to_delete = old_members
+        .iter()
+        .filter(|member| !member_ids.contains(member.id.as_str()))
+        .cloned()
+        .collect_vec();
+
+    let num_old_accounts = old_members.len();
+    let num_new_accounts = members.len();
+
+    limits
+        .check_removals(num_old_accounts, to_delete.len())
+        .map_err(|e| SyncError::SafetyLimit(format!("list {:?}: {}", list.title, e)))?;
+
+    if !to_add.is_empty() || !to_delete.is_empty() {
+        snapshots.save(&client.host, list, old_members).await?;
+    }
+
+    for account_chunk in to_add.chunks(UPDATE_CHUNK_SIZE) {
+        let account_ids = account_chunk
+            .iter()
+            .map(|member| member.id.clone())
+            .collect_vec();
+        tracing::debug!(
+            "syncing list {} ({}): adding accounts: {:?}",
+            list.id,
+            list.title,
+            account_ids
+        );
+
+        client
+            .post(
+                &format!("/api/v1/lists/{}/accounts", list.id),
+                Box::new(move |builder| {
+                    builder.form(
+                        &account_ids
+                            .iter()
+                            .map(|id| ("account_ids[]", id))
+                            .collect_vec(),
+                    )
+                }),
+            )
+            .await
+            .list_context(&list.id, "failed to add members")?
+            .error_for_status()
+            .list_context(&list.id, "failed to add members")?;
+    }
+
+    for account_chunk in to_delete.chunks(UPDATE_CHUNK_SIZE) {
+        let account_ids = account_chunk
+            .iter()
+            .map(|member| member.id.clone())
+            .collect_vec();
+        tracing::debug!(
+            "syncing list {} ({}): deleting accounts: {:?}",
+            list.id,
+            list.title,
+            account_ids
         );
 
-        Ok(())
+        client
+            .delete(
+                &format!("/api/v1/lists/{}/accounts", list.id),
+                Box::new(move |builder| {
+                    builder.form(
+                        &account_ids
+                            .iter()
+                            .map(|id| ("account_ids[]", id))
+                            .collect_vec(),
+                    )
+                }),
+            )
+            .await
+            .list_context(&list.id, "failed to remove members")?
+            .error_for_status()
+            .list_context(&list.id, "failed to remove members")?;
     }
+
+    tracing::info!(
+        "done syncing, went from {} to {} members",
+        num_old_accounts,
+        num_new_accounts
+    );
+
+    snapshots
+        .save_record(
+            &client.host,
+            &ListRecord {
+                list_id: list.id.clone(),
+                list_title: list.title.clone(),
+                verified_at,
+                members: members.to_vec(),
+            },
+        )
+        .await?;
+
+    Ok(MembershipChange {
+        member_count_before: num_old_accounts,
+        member_count: num_new_accounts,
+        added: to_add,
+        removed: to_delete,
+        incremental: false,
+    })
 }
 
 #[test]
@@ -307,3 +769,77 @@ fn parsing_and() {
         ]))
     );
 }
+
+#[test]
+fn display() {
+    let terms = ListManagerTerms::from_str("hello #last_status_at > 1w&&mutuals").unwrap();
+    assert_eq!(terms.to_string(), "#last_status_at>7d & mutuals");
+    assert_eq!(ListManagerTerms::from_str(&terms.to_string()), Ok(terms));
+}
+
+#[test]
+fn describe() {
+    let terms = ListManagerTerms::from_str("#mutuals & last_status_at>3d").unwrap();
+    assert_eq!(
+        terms.describe(),
+        "mutuals AND last post more than 3 days ago"
+    );
+    assert_eq!(ListManagerTerms::from_title("no filter"), None);
+    assert!(matches!(
+        ListManagerTerms::from_title("broken #mutual"),
+        Some(Err(_))
+    ));
+}
+
+#[test]
+fn serialize() {
+    let terms = ListManagerTerms::from_str("#last_status_at<2w & mutuals").unwrap();
+    assert_eq!(terms.to_string(), "#last_status_at<14d & mutuals");
+    assert_eq!(
+        serde_json::to_value(&terms).unwrap(),
+        serde_json::json!([
+            { "type": "last_status", "is_gt": false, "days": 14 },
+            { "type": "mutuals" }
+        ])
+    );
+}
+
+#[test]
+fn last_status() {
+    let cutoff = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+    let before = NaiveDate::from_ymd_opt(2024, 4, 1).unwrap();
+    let after = NaiveDate::from_ymd_opt(2024, 6, 1).unwrap();
+
+    assert!(last_status_matches(true, cutoff, Some(before)));
+    assert!(!last_status_matches(true, cutoff, Some(after)));
+    assert!(last_status_matches(true, cutoff, None));
+    assert!(last_status_matches(false, cutoff, Some(after)));
+    assert!(!last_status_matches(false, cutoff, Some(before)));
+    assert!(!last_status_matches(false, cutoff, None));
+}
+
+#[test]
+fn parsing_exact() {
+    assert_eq!(
+        ListManagerTerms::parse_exact("Friends #mutuals & last_status_at<2w "),
+        Ok(ListManagerTerms(vec![
+            ListManagerTerm::Mutuals,
+            ListManagerTerm::LastStatus {
+                is_gt: false,
+                days: Days::new(14)
+            }
+        ]))
+    );
+
+    let error = |title| ListManagerTerms::parse_exact(title).unwrap_err().position;
+    assert_eq!(error("Friends"), 7);
+    assert_eq!(error("#mutual"), 1);
+    assert_eq!(error("#mutuals & foo"), 11);
+    assert_eq!(error("#last_status_at=1d"), 15);
+    assert_eq!(error("#last_status_at>0d"), 16);
+    assert_eq!(error("#mutuals (friends)"), 9);
+
+    // lenient parsing accepts these
+    assert!(ListManagerTerms::from_str("#mutuals & foo").is_ok());
+    assert!(ListManagerTerms::from_str("#mutuals (friends)").is_ok());
+}
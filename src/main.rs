@@ -1,19 +1,25 @@
+use std::collections::BTreeMap;
 use std::net::SocketAddr;
 use std::str::FromStr;
-use std::time::Duration;
+use std::sync::Arc;
 
-use anyhow::Error;
-use api_client::ApiClient;
+use anyhow::{Context, Error};
 use axum::{
     body::Body,
     debug_handler,
-    extract::{Host, Query, State},
+    extract::{Query, State},
+    http::{
+        header::{CONTENT_DISPOSITION, CONTENT_TYPE},
+        StatusCode,
+    },
+    response::sse::{Event, KeepAlive, Sse},
     response::{Html, IntoResponse, Redirect, Response},
     routing::{get, post},
-    Form, Router,
+    Form, Json, Router,
 };
 use clap::Parser;
-use maud::Markup;
+use futures_util::stream;
+use maud::{Markup, PreEscaped};
 use serde::{Deserialize, Serialize};
 use tower_sessions::{Expiry, Session};
 
@@ -23,19 +29,41 @@ mod api_helpers;
 mod api_models;
 mod auth;
 mod config;
+mod crypto;
+mod csrf;
+mod daemon;
 mod error;
+mod filter_builder;
+mod hosts;
+mod instance;
 mod list_manager;
+mod metrics;
+mod progress;
 mod runner;
+mod safety;
+mod schedule;
+mod scheduler;
+mod snapshot;
 mod store;
+mod validate;
+mod web_api;
 
-use config::Server;
-use config::{Cli, Subcommand};
-use error::ResponseError;
+use config::{AccountsFile, OutputFormat, Server};
+use config::{Cli, Snapshot, Subcommand};
+use csrf::CsrfToken;
+use error::{describe_sync_error, ResponseError};
+use hosts::HostCoordinator;
+use list_manager::ListManagerTerms;
 use memory_serve::{load_assets, MemoryServe};
+use progress::SyncProgress;
+use runner::RunOptions;
+use schedule::{Schedule, SyncFrequency};
+use scheduler::SchedulerStatus;
+use snapshot::SnapshotTarget;
 use store::{AccountPk, RegisterAccount, SyncImmediateResult};
 use tower_sessions::{MemoryStore, SessionManagerLayer};
 
-use crate::auth::{LoggedIn, SESSION_COOKIE_KEY};
+use crate::auth::{LoggedIn, PendingLogin, OAUTH_SCOPES, OAUTH_SESSION_KEY, SESSION_COOKIE_KEY};
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
@@ -47,19 +75,122 @@ async fn main() -> Result<(), Error> {
     });
 
     tracing_subscriber::registry()
-        .with(tracing_subscriber::fmt::layer())
+        // stdout is reserved for output such as `run-once --output json`
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
         .with(tracing_subscriber::EnvFilter::from_env("RUST_LOG"))
         .with(sentry::integrations::tracing::layer())
         .init();
 
     let cli = Cli::parse();
+    let public_only = match &cli.subcommand {
+        Subcommand::Serve(server) => !server.allow_private_hosts,
+        _ => false,
+    };
+    let client_config = cli.http.client_config(public_only)?;
+    let hosts = HostCoordinator::new(client_config);
 
     match cli.subcommand {
         Subcommand::RunOnce(run_once_cli) => {
-            runner::run_once(&run_once_cli.host, &run_once_cli.token).await?;
+            let (host, token) = (&run_once_cli.host, &run_once_cli.token);
+            let options = RunOptions {
+                snapshots: SnapshotTarget::Directory(run_once_cli.snapshots.snapshot_dir),
+                limits: run_once_cli.safety.limits(run_once_cli.force),
+                incremental: run_once_cli.incremental.incremental,
+                hosts,
+                ..RunOptions::default()
+            };
+            match run_once_cli.output {
+                OutputFormat::Text => {
+                    runner::run_once(host, token, &options).await?;
+                }
+                OutputFormat::Json => {
+                    let report = runner::run_once_report(host, token, &options).await;
+                    println!("{}", serde_json::to_string_pretty(&report)?);
+                    if let Some(error) = report.error {
+                        anyhow::bail!("sync failed: {}", error.message);
+                    }
+                }
+            }
+        }
+        Subcommand::Run(run_cli) => {
+            let config = AccountsFile::load(&run_cli.config)?;
+            let options = RunOptions {
+                snapshots: SnapshotTarget::Directory(run_cli.snapshots.snapshot_dir),
+                limits: run_cli.safety.limits(run_cli.force),
+                incremental: run_cli.incremental.incremental,
+                hosts,
+                ..RunOptions::default()
+            };
+            let failures = runner::run_accounts(&config, &options).await;
+            if failures > 0 {
+                anyhow::bail!(
+                    "{} of {} accounts failed to sync",
+                    failures,
+                    config.accounts.len()
+                );
+            }
+        }
+        Subcommand::Daemon(daemon_cli) => {
+            let config = AccountsFile::load(&daemon_cli.config)?;
+            let state_path = daemon_cli
+                .snapshots
+                .snapshot_dir
+                .join("daemon")
+                .join("state.json");
+            let options = RunOptions {
+                snapshots: SnapshotTarget::Directory(daemon_cli.snapshots.snapshot_dir),
+                limits: daemon_cli.safety.limits(false),
+                incremental: daemon_cli.incremental.incremental,
+                hosts,
+                ..RunOptions::default()
+            };
+            daemon::run(
+                &config,
+                std::time::Duration::from_secs(daemon_cli.interval),
+                &state_path,
+                options,
+            )
+            .await?;
+        }
+        Subcommand::Explain(explain_cli) => {
+            runner::explain(
+                &hosts,
+                &explain_cli.host,
+                &explain_cli.token,
+                &explain_cli.list,
+                &explain_cli.account,
+            )
+            .await?;
+        }
+        Subcommand::Validate(validate_cli) => match (validate_cli.expression, validate_cli.host) {
+            (Some(expression), _) => {
+                if !validate::validate_expression(&expression) {
+                    anyhow::bail!("invalid filter");
+                }
+            }
+            (None, Some(host)) => {
+                let token = validate_cli
+                    .token
+                    .context("--lists needs a token, pass --token or set LIST_BOT_TOKEN")?;
+                let broken = validate::validate_lists(&hosts, &host, &token).await?;
+                if broken > 0 {
+                    anyhow::bail!("{} lists have invalid filters", broken);
+                }
+            }
+            (None, None) => unreachable!("enforced by clap"),
+        },
+        Subcommand::Snapshot(Snapshot::List { snapshots }) => {
+            snapshot::print_directory(&snapshots.snapshot_dir)?;
+        }
+        Subcommand::Snapshot(Snapshot::Restore {
+            id,
+            snapshots,
+            token,
+        }) => {
+            snapshot::restore_from_directory(&hosts, &snapshots.snapshot_dir, &id, &token).await?;
         }
         Subcommand::Serve(server) => {
-            serve(server).await?;
+            serve(server, hosts).await?;
         }
     }
 
@@ -69,28 +200,32 @@ async fn main() -> Result<(), Error> {
 #[derive(Clone)]
 struct AppState {
     store: store::Store,
+    scheduler: Arc<SchedulerStatus>,
+    allow_private_hosts: bool,
+    /// `Server::public_url` without a trailing slash.
+    public_url: String,
 }
 
-async fn serve(server_cli: Server) -> Result<(), Error> {
+async fn serve(server_cli: Server, hosts: HostCoordinator) -> Result<(), Error> {
     let socketaddr_str = format!("{}:{}", server_cli.addr, server_cli.port);
 
-    let store = store::Store::new(&server_cli.database).await?;
-    let cronjob_store = store.clone();
-
-    let state = AppState { store };
-
-    let _cronjob = tokio::spawn(async move {
-        loop {
-            match cronjob_store.sync_all_accounts().await {
-                Ok((success, failure)) => {
-                    tracing::info!("cronjob: {} success, {} failure", success, failure)
-                }
-                Err(e) => tracing::error!("failed to run cronjob: {:?}", e),
-            }
+    let cipher = server_cli.token_cipher()?;
+    let store = store::Store::new(
+        &server_cli.database,
+        cipher,
+        server_cli.safety.limits(false),
+        server_cli.incremental.incremental,
+        hosts,
+    )
+    .await?;
+    let scheduler = scheduler::spawn(store.clone());
 
-            tokio::time::sleep(Duration::from_secs(3600)).await;
-        }
-    });
+    let state = AppState {
+        store,
+        scheduler,
+        allow_private_hosts: server_cli.allow_private_hosts,
+        public_url: server_cli.public_url.trim_end_matches('/').to_owned(),
+    };
 
     let session_store = MemoryStore::default();
     let session_layer = SessionManagerLayer::new(session_store)
@@ -105,11 +240,30 @@ async fn serve(server_cli: Server) -> Result<(), Error> {
     let app = Router::new()
         .merge(static_files)
         .route("/", get(index))
+        .route("/metrics", get(metrics_endpoint))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
         .route("/account/login", post(account_login))
         .route("/account/logout", post(account_logout))
+        .route("/account/export", get(account_export))
+        .route("/account/delete", post(account_delete))
         .route("/account/sync-immediate", post(sync_immediate))
+        .route("/account/sync-progress", get(sync_progress))
+        .route("/account/resume", post(account_resume))
+        .route("/account/schedule", post(account_schedule))
         .route("/account/oauth-redirect", get(account_redirect))
         .route("/account/admin", get(account_admin))
+        .route("/account/lists", get(account_lists))
+        .route("/account/revert-sync", post(account_revert_sync))
+        .route(
+            "/account/filter-builder/preview",
+            post(filter_builder::preview),
+        )
+        .route("/account/filter-builder/save", post(filter_builder::save))
+        .route("/account/api-tokens", post(account_create_api_token))
+        .route("/account/api-tokens/delete", post(account_delete_api_token))
+        .nest("/api/v1", web_api::router())
+        .layer(axum::middleware::from_fn(csrf::verify))
         .layer(session_layer)
         .with_state(state);
 
@@ -127,13 +281,14 @@ async fn serve(server_cli: Server) -> Result<(), Error> {
     Ok(())
 }
 
-async fn index() -> Response {
+async fn index(csrf: CsrfToken) -> Response {
     let html = maud::html! {
         p {
             "Create programmatic lists in " a href="https://joinmastodon.org" { "Mastodon" } ". Take a look at the " a href="https://github.com/untitaker/mastodon-list-bot" { "GitHub project" } " for more information."
         }
 
         form action="/account/login" method="post" {
+            (csrf.field())
             fieldset role="group" {
                 input
                     type="text"
@@ -151,102 +306,334 @@ async fn index() -> Response {
     Html(with_site_chrome(html).into_string()).into_response()
 }
 
+#[derive(Deserialize)]
+struct SyncImmediateForm {
+    /// Set by the "Sync anyway" button, after a sync hit the safety limits.
+    force: Option<String>,
+}
+
 async fn sync_immediate(
     State(state): State<AppState>,
     login: LoggedIn,
+    form: Option<Form<SyncImmediateForm>>,
 ) -> Result<Response, ResponseError> {
     let account_pk = login.account()?;
-    let body = state.store.sync_immediate(account_pk).await?;
+    let force = form.is_some_and(|Form(form)| form.force.is_some());
+    let body = state.store.sync_immediate(account_pk, force).await?;
 
     let html: maud::Markup = match body {
         SyncImmediateResult::Ok => maud::html! {
             p { "Done syncing! Refresh the page to see results. Future updates to your lists will happen automatically." }
         },
-        SyncImmediateResult::Error { value } => maud::html! {
-            p.red { "Error: "(value) }
-        },
+        SyncImmediateResult::Error { code, value } => {
+            let (summary, action) = describe_sync_error(code);
+            maud::html! {
+                p.red { "Error: "(summary)" "(action) }
+                details {
+                    summary { "Details" }
+                    code { (value) }
+                }
+                @if code == "safety_limit" {
+                    button.secondary
+                        data-hx-post="/account/sync-immediate"
+                        data-hx-vals=r#"{"force": "1"}"#
+                        data-hx-target="#sync-result"
+                        data-hx-swap="innerHTML" { "Sync anyway" }
+                }
+            }
+        }
         SyncImmediateResult::Pending => maud::html! {
-            p { "Sync ongoing." }
+            p data-sync-progress { "Sync ongoing." }
         },
         SyncImmediateResult::TooMany => maud::html! {
             p { "Sync has been done recently, not starting another one." }
         },
+        SyncImmediateResult::NeedsReauth => maud::html! {
+            p.red { "Your instance rejected our access token. Please log in again before syncing." }
+        },
     };
 
     Ok(Html(html.into_string()).into_response())
 }
 
-#[derive(Deserialize)]
-struct AccountRegister {
-    host: String,
+/// Streams progress of the running immediate sync as server-sent events. Sends a single
+/// `finished` event if no sync is running.
+async fn sync_progress(
+    State(state): State<AppState>,
+    login: LoggedIn,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    let receiver = state.store.subscribe_progress(&account_pk).await;
+
+    let events = stream::unfold(Some((receiver, true)), |state| async move {
+        let (receiver, first) = state?;
+
+        let (progress, receiver) = match receiver {
+            Some(mut receiver) => {
+                if !first && receiver.changed().await.is_err() {
+                    return None;
+                }
+                let progress = receiver.borrow_and_update().clone();
+                let finished = matches!(progress, SyncProgress::Finished);
+                (progress, (!finished).then_some(receiver))
+            }
+            None => (SyncProgress::Finished, None),
+        };
+
+        let event = Event::default()
+            .event(match progress {
+                SyncProgress::Finished => "finished",
+                _ => "progress",
+            })
+            .data(progress.describe());
+
+        let next = receiver.map(|receiver| (Some(receiver), false));
+        Some((Ok::<_, std::convert::Infallible>(event), next))
+    });
+
+    Ok(Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response())
 }
 
-#[derive(Deserialize, Serialize)]
-struct OauthState {
-    client_id: String,
-    client_secret: String,
-    host: String,
+async fn metrics_endpoint(State(state): State<AppState>) -> Result<Response, ResponseError> {
+    state.store.update_account_metrics().await?;
+    let body = metrics::METRICS.encode()?;
+    Ok((
+        [(CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")],
+        body,
+    )
+        .into_response())
 }
 
-fn get_service_uri(Host(self_host): Host) -> String {
-    if cfg!(debug_assertions) {
-        format!("http://{self_host}")
-    } else {
-        format!("https://{self_host}")
+#[derive(Serialize)]
+struct HealthStatus {
+    ok: bool,
+    database: String,
+    scheduler_ok: bool,
+    scheduler_last_cycle_at: Option<chrono::NaiveDateTime>,
+    scheduler_last_heartbeat_at: Option<chrono::NaiveDateTime>,
+    scheduler_restarts: u64,
+}
+
+impl HealthStatus {
+    async fn check(state: &AppState) -> Self {
+        let database = match state.store.ping().await {
+            Ok(()) => "ok".to_owned(),
+            Err(e) => e.to_string(),
+        };
+        let scheduler = &state.scheduler;
+
+        HealthStatus {
+            ok: database == "ok",
+            database,
+            scheduler_ok: scheduler.is_healthy(chrono::Utc::now().naive_utc()),
+            scheduler_last_cycle_at: scheduler.last_cycle_at(),
+            scheduler_last_heartbeat_at: scheduler.last_heartbeat_at(),
+            scheduler_restarts: scheduler.restarts(),
+        }
+    }
+}
+
+impl IntoResponse for HealthStatus {
+    fn into_response(self) -> Response {
+        let status = if self.ok {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        (status, Json(self)).into_response()
     }
 }
 
+/// Liveness: fails if the database is unreachable or the scheduler task stopped responding.
+async fn healthz(State(state): State<AppState>) -> Response {
+    let mut health = HealthStatus::check(&state).await;
+    health.ok &= health.scheduler_ok;
+    health.into_response()
+}
+
+/// Readiness: fails if the database is unreachable.
+async fn readyz(State(state): State<AppState>) -> Response {
+    HealthStatus::check(&state).await.into_response()
+}
+
+async fn account_resume(
+    State(state): State<AppState>,
+    login: LoggedIn,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    state.store.reset_backoff(&account_pk).await?;
+    Ok(Redirect::to("/account/admin").into_response())
+}
+
+#[derive(Deserialize)]
+struct AccountSchedule {
+    frequency: String,
+    #[serde(default)]
+    cron: String,
+    #[serde(default)]
+    preferred_hour: String,
+}
+
+async fn account_schedule(
+    State(state): State<AppState>,
+    login: LoggedIn,
+    Form(form): Form<AccountSchedule>,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+
+    let frequency = if form.frequency == "cron" {
+        format!("cron:{}", form.cron)
+    } else {
+        form.frequency
+    };
+
+    let preferred_hour = match form.preferred_hour.as_str() {
+        "" => None,
+        hour => Some(
+            hour.parse()
+                .map_err(|_| ResponseError::BadRequest(format!("invalid hour {:?}", hour)))?,
+        ),
+    };
+
+    let schedule = frequency
+        .parse()
+        .and_then(|frequency| Schedule::new(frequency, preferred_hour))
+        .map_err(ResponseError::BadRequest)?;
+
+    state.store.set_schedule(&account_pk, schedule).await?;
+    Ok(Redirect::to("/account/admin").into_response())
+}
+
+#[derive(Deserialize)]
+struct CreateApiToken {
+    name: String,
+}
+
+async fn account_create_api_token(
+    State(state): State<AppState>,
+    login: LoggedIn,
+    Form(CreateApiToken { name }): Form<CreateApiToken>,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    let token = state.store.create_api_token(&account_pk, &name).await?;
+
+    let html = maud::html! {
+        h2 { "New API token" }
+        p { "This is your new API token. Copy it now, it will not be shown again." }
+        pre { code { (token) } }
+        p {
+            "Pass it as "
+            code { "Authorization: Bearer ..." }
+            " header to the endpoints under "
+            code { "/api/v1/" }
+            "."
+        }
+        a href="/account/admin" { "Back" }
+    };
+
+    Ok(Html(with_site_chrome(html).into_string()).into_response())
+}
+
+#[derive(Deserialize)]
+struct DeleteApiToken {
+    id: i64,
+}
+
+async fn account_delete_api_token(
+    State(state): State<AppState>,
+    login: LoggedIn,
+    Form(DeleteApiToken { id }): Form<DeleteApiToken>,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    state.store.delete_api_token(&account_pk, id).await?;
+    Ok(Redirect::to("/account/admin").into_response())
+}
+
+#[derive(Deserialize)]
+struct AccountRegister {
+    host: String,
+    /// Set once the user has seen the warnings about their server software.
+    confirmed: Option<String>,
+}
+
 async fn account_login(
-    self_host: Host,
-    Form(AccountRegister { host }): Form<AccountRegister>,
+    session: Session,
+    State(state): State<AppState>,
+    csrf: CsrfToken,
+    Form(AccountRegister { host, confirmed }): Form<AccountRegister>,
 ) -> Result<Response, ResponseError> {
-    let service_uri = get_service_uri(self_host);
-    let self_redirect_uri = format!("{service_uri}/account/oauth-redirect");
+    let host = instance::normalize_host(&host).map_err(ResponseError::BadRequest)?;
+    if !state.allow_private_hosts {
+        instance::check_public(&host).await?;
+    }
 
-    let client = ApiClient::new(&host, None).unwrap();
-    let scopes = "read:follows read:lists read:accounts write:lists";
+    if confirmed.is_none() {
+        let software = instance::detect_software(state.store.hosts(), &host).await?;
+        let warnings = software.warnings();
 
-    #[derive(Deserialize)]
-    struct OauthAppResponse {
-        client_id: String,
-        client_secret: String,
+        if !warnings.is_empty() {
+            let html = maud::html! {
+                h2 { (host)" runs "(software) }
+                @for warning in &warnings {
+                    p { (warning) }
+                }
+                form action="/account/login" method="post" {
+                    (csrf.field())
+                    input type="hidden" name="host" value=(host);
+                    input type="hidden" name="confirmed" value="1";
+                    div.grid {
+                        a href="/" role="button" class="secondary outline" { "Cancel" }
+                        input type="submit" value="Continue anyway";
+                    }
+                }
+            };
+
+            return Ok(Html(with_site_chrome(html).into_string()).into_response());
+        }
     }
 
-    let OauthAppResponse {
-        client_id,
-        client_secret,
-    } = client
-        .client
-        .post(format!("https://{host}/api/v1/apps"))
-        .form(&[
-            ("client_name", "Mastodon List Bot"),
-            ("website", &service_uri),
-            ("scopes", scopes),
-            ("redirect_uris", &self_redirect_uri),
-        ])
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
+    let service_uri = &state.public_url;
+    let self_redirect_uri = format!("{service_uri}/account/oauth-redirect");
+
+    let app = state
+        .store
+        .get_or_register_oauth_app(&host, service_uri, &self_redirect_uri)
         .await?;
 
-    let state = OauthState {
-        client_id: client_id.clone(),
-        client_secret: client_secret.clone(),
+    let pending = PendingLogin {
         host: host.clone(),
+        client_id: app.client_id.clone(),
+        state: crypto::random_token("")?,
+        code_verifier: crypto::random_token("")?,
     };
-    // there is no point in hiding the client secret from the user. we already create one secret
-    // per user to begin with, and mastodon allows anybody to register multiple applications with
-    // the same name/website/logo. backend-less SPAs already work like this, and it's a waste of
-    // resources to make a separate "tokens" table mapping host -> client ID/secret
-    let state = serde_json::to_string(&state).unwrap();
-    let state = data_encoding::BASE64URL_NOPAD.encode(state.as_bytes());
 
-    let foreign_redirect_uri = format!("https://{host}/oauth/authorize?scope={scopes}&response_type=code&redirect_uri={self_redirect_uri}&client_id={client_id}&client_secret={client_secret}&state={state}");
+    let foreign_redirect_uri = reqwest::Url::parse_with_params(
+        &format!(
+            "{}/oauth/authorize",
+            state.store.hosts().config().base_url(&host)
+        ),
+        &[
+            ("scope", OAUTH_SCOPES),
+            ("response_type", "code"),
+            ("redirect_uri", &self_redirect_uri),
+            ("client_id", &app.client_id),
+            ("state", &pending.state),
+            (
+                "code_challenge",
+                &crypto::pkce_challenge(&pending.code_verifier),
+            ),
+            ("code_challenge_method", "S256"),
+        ],
+    )
+    .map_err(|e| ResponseError::BadRequest(format!("invalid host {:?}: {}", host, e)))?;
+
+    session.insert(OAUTH_SESSION_KEY, pending).await?;
 
     Ok(Response::builder()
         .status(302)
-        .header("Location", foreign_redirect_uri)
+        .header("Location", foreign_redirect_uri.as_str())
         .body(Body::empty())
         .unwrap())
 }
@@ -266,45 +653,66 @@ struct OauthAccountRedirect {
 #[debug_handler]
 async fn account_redirect(
     session: Session,
-    self_host: Host,
     State(state): State<AppState>,
     Query(OauthAccountRedirect {
         code,
         state: oauth_state,
     }): Query<OauthAccountRedirect>,
 ) -> Result<Response, ResponseError> {
-    let service_uri = get_service_uri(self_host);
-    let self_redirect_uri = format!("{service_uri}/account/oauth-redirect");
+    let self_redirect_uri = format!("{}/account/oauth-redirect", state.public_url);
 
-    let oauth_state = data_encoding::BASE64URL_NOPAD.decode(oauth_state.as_bytes())?;
-    let OauthState {
-        client_id,
-        client_secret,
-        host,
-    } = serde_json::from_slice(&oauth_state)?;
-    let client = ApiClient::new(&host, None).unwrap();
+    // the pending login is single-use
+    let pending: PendingLogin = session
+        .remove(OAUTH_SESSION_KEY)
+        .await?
+        .filter(|pending: &PendingLogin| pending.state == oauth_state)
+        .ok_or_else(|| {
+            ResponseError::BadRequest(
+                "this login has expired or was started in another browser, please try again"
+                    .to_owned(),
+            )
+        })?;
+    let host = pending.host;
+
+    let app = state
+        .store
+        .get_oauth_app(&host)
+        .await?
+        .filter(|app| app.client_id == pending.client_id)
+        .ok_or_else(|| {
+            ResponseError::BadRequest(
+                "the app registered for this instance changed during login, please try again"
+                    .to_owned(),
+            )
+        })?;
+    let client = state.store.hosts().client(&host, None)?;
 
     #[derive(Deserialize)]
     struct OauthTokenResponse {
         access_token: String,
     }
 
-    let OauthTokenResponse { access_token } = client
+    let res = client
         .client
-        .post(format!("https://{host}/oauth/token"))
+        .post(client.url("/oauth/token"))
         .form(&[
-            ("client_id", client_id.as_str()),
-            ("client_secret", client_secret.as_str()),
+            ("client_id", app.client_id.as_str()),
+            ("client_secret", app.client_secret.as_str()),
             ("grant_type", "authorization_code"),
             ("code", &code),
             ("redirect_uri", &self_redirect_uri),
+            ("code_verifier", &pending.code_verifier),
         ])
         .send()
-        .await?
-        .error_for_status()?
-        .json()
         .await?;
 
+    if res.status() == StatusCode::UNAUTHORIZED {
+        // the instance forgot about our app, register a new one on the next login
+        state.store.delete_oauth_app(&host).await?;
+    }
+
+    let OauthTokenResponse { access_token } = res.error_for_status()?.json().await?;
+
     let register_account = RegisterAccount {
         host,
         token: access_token,
@@ -321,42 +729,80 @@ async fn account_redirect(
 async fn account_admin(
     State(state): State<AppState>,
     login: LoggedIn,
+    csrf: CsrfToken,
 ) -> Result<Response, ResponseError> {
     let account_pk = login.account()?;
-    let account = state.store.get_account(account_pk).await?;
+    let account = state.store.get_account(account_pk.clone()).await?;
+    let api_tokens = state.store.get_api_tokens(&account_pk).await?;
+    let schedule = account.schedule();
+    let cron_expr = match &schedule.frequency {
+        SyncFrequency::Cron(cron) => Some(cron.to_string()),
+        _ => None,
+    };
 
     let html = maud::html! {
-        div {
+        div data-hx-headers=(csrf.htmx_headers()) {
             div.grid {
                 h2 { "Hello "(account.username)"@"(account.host)"!" }
                 form
                     method="post"
                     action="/account/logout" {
+                        (csrf.field())
                         input.secondary.outline type="submit" value="Logout";
                     }
             }
 
-            @if account.failure_count > 0 {
+            @if account.needs_reauth {
+                article {
+                    p."pico-color-red-500" {
+                        "Your instance rejected our access token, most likely because the app was revoked in your account settings. Syncing is paused until you log in again."
+                    }
+                    form action="/account/login" method="post" {
+                        (csrf.field())
+                        input type="hidden" name="host" value=(account.host);
+                        input type="submit" value="Re-authorize";
+                    }
+                }
+            } @else if account.is_paused() {
+                article {
+                    p."pico-color-red-500" {
+                        "Syncing is paused after "(account.failure_count)" failed attempts in a row."
+                    }
+                    form action="/account/resume" method="post" {
+                        (csrf.field())
+                        input type="submit" value="Resume syncing";
+                    }
+                }
+            } @else if account.failure_count > 0 {
                 p.red {
-                    "We have encountered "(account.failure_count)" fatal errors when trying to sync. After 10 attempts, we will stop synchronizing."
+                    "We have encountered "(account.failure_count)" errors when trying to sync. "
+                    @if let Some(d) = account.next_attempt_at {
+                        "We will try again at "(d)". "
+                    }
+                    "After "(store::MAX_FAILURES)" attempts, syncing is paused."
                 }
             }
 
             @if let Some(err) = account.last_error {
+                @let (summary, action) = describe_sync_error(account.last_error_code.as_deref().unwrap_or("internal"));
                 p."pico-color-red-500" {
-                    "The last error we encountered was: "(err)
+                    "The last error we encountered was: "(summary)" "(action)
+                }
+                details {
+                    summary { "Details" }
+                    code { (err) }
                 }
             }
 
             @if let Some(d) = account.last_success_at {
                 p { "Your last successful sync was at "(d)"." }
-                p { (account.list_count)" dynamic lists were found." }
+                p { (account.list_count)" dynamic lists were found. " a href="/account/lists" { "Show lists" } }
             } @else {
                 p { "Not synced yet." }
             }
 
             p {
-                "Your lists will be updated once per day. Take a look at the " a href="https://github.com/untitaker/mastodon-list-bot#how-to-use" { "README" } " to see which list names are supported. After that, click Sync Now."
+                "Your lists will be updated "(schedule.describe())". Take a look at the " a href="https://github.com/untitaker/mastodon-list-bot#how-to-use" { "README" } " to see which list names are supported, or use the " a href="/account/lists#filter-builder" { "filter builder" } ". After that, click Sync Now."
             }
 
             form
@@ -367,9 +813,95 @@ async fn account_admin(
             data-hx-swap="innerHTML"
             data-hx-target="#sync-result"
             data-hx-disabled-elt="#sync-now" {
+                (csrf.field())
                 input id="sync-now" type="submit" value="Sync now";
                 p id="sync-result";
             }
+            script { (PreEscaped(SYNC_PROGRESS_SCRIPT)) }
+
+            details {
+                summary { "Change schedule" }
+                form method="post" action="/account/schedule" {
+                    (csrf.field())
+                    label {
+                        "Frequency"
+                        select name="frequency" {
+                            option value="6h" selected[schedule.frequency == SyncFrequency::EverySixHours] { "Every 6 hours" }
+                            option value="daily" selected[schedule.frequency == SyncFrequency::Daily] { "Daily" }
+                            option value="weekly" selected[schedule.frequency == SyncFrequency::Weekly] { "Weekly" }
+                            option value="cron" selected[matches!(schedule.frequency, SyncFrequency::Cron(_))] { "Custom (cron expression)" }
+                        }
+                    }
+                    label {
+                        "Cron expression (UTC, only for custom)"
+                        input
+                            type="text"
+                            name="cron"
+                            placeholder="e.g. 0 6 * * 1-5"
+                            value=[cron_expr];
+                    }
+                    label {
+                        "Preferred hour (UTC, only for daily and weekly)"
+                        select name="preferred_hour" {
+                            option value="" selected[schedule.preferred_hour.is_none()] { "Any" }
+                            @for hour in 0..24 {
+                                option value=(hour) selected[schedule.preferred_hour == Some(hour)] { (format!("{:02}:00", hour)) }
+                            }
+                        }
+                    }
+                    small { "Lists are never synced more often than every 6 hours." }
+                    input type="submit" value="Save schedule";
+                }
+            }
+
+            details {
+                summary { "API tokens" }
+                p {
+                    "Personal API tokens give scripts access to the JSON API under "
+                    code { "/api/v1/" }
+                    "."
+                }
+                @for token in &api_tokens {
+                    form.grid method="post" action="/account/api-tokens/delete" {
+                        (csrf.field())
+                        span { (token.name) }
+                        small {
+                            "created "(token.created_at)
+                            @if let Some(d) = token.last_used_at {
+                                ", last used "(d)
+                            }
+                        }
+                        input type="hidden" name="id" value=(token.id);
+                        input.secondary.outline type="submit" value="Revoke";
+                    }
+                }
+                form method="post" action="/account/api-tokens" {
+                    (csrf.field())
+                    fieldset role="group" {
+                        input type="text" required name="name" placeholder="Token name";
+                        input type="submit" value="Create token";
+                    }
+                }
+            }
+
+            details {
+                summary { "Your data" }
+                p {
+                    a href="/account/export" download { "Download your data" }
+                    " as JSON: your sync settings, sync history, list statistics, list snapshots and API tokens. Your access token is not included."
+                }
+                form method="post" action="/account/delete" {
+                    (csrf.field())
+                    p {
+                        "Deleting your account revokes our access token and removes everything we store about you. Your lists on your instance are left as they are."
+                    }
+                    label {
+                        input type="checkbox" required name="confirm";
+                        "I want to delete my account"
+                    }
+                    input.secondary type="submit" value="Delete account";
+                }
+            }
 
             script src="/htmx.js" {}
         }
@@ -378,6 +910,210 @@ async fn account_admin(
     Ok(Html(with_site_chrome(html).into_string()).into_response())
 }
 
+async fn account_export(
+    State(state): State<AppState>,
+    login: LoggedIn,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    let export = state.store.export_account(&account_pk).await?;
+    let filename = format!("mastodon-list-bot-{}-{}.json", export.username, export.host);
+
+    Ok((
+        [(
+            CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )],
+        Json(export),
+    )
+        .into_response())
+}
+
+#[derive(Deserialize)]
+struct AccountDelete {
+    confirm: Option<String>,
+}
+
+async fn account_delete(
+    session: Session,
+    State(state): State<AppState>,
+    login: LoggedIn,
+    Form(form): Form<AccountDelete>,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    if form.confirm.is_none() {
+        return Err(ResponseError::BadRequest(
+            "please confirm that you want to delete your account".to_owned(),
+        ));
+    }
+
+    let revoked = state.store.delete_account(&account_pk).await?;
+    session.remove::<AccountPk>(SESSION_COOKIE_KEY).await?;
+
+    let html = maud::html! {
+        h2 { "Your account was deleted" }
+        p { "We no longer store any data about your account." }
+        @if !revoked {
+            p {
+                "We could not revoke our access token. Please revoke \"Mastodon List Bot\" under Preferences → Account → Authorized apps on your instance."
+            }
+        }
+        p { a href="/" { "Back to the start page" } }
+    };
+
+    Ok(Html(with_site_chrome(html).into_string()).into_response())
+}
+
+#[debug_handler]
+async fn account_lists(
+    State(state): State<AppState>,
+    login: LoggedIn,
+    csrf: CsrfToken,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    let account = state.store.get_account(account_pk.clone()).await?;
+    let client = state.store.api_client(&account)?;
+    let lists = runner::get_lists(&client).await?;
+    let stats: BTreeMap<_, _> = state
+        .store
+        .get_list_stats(&account_pk)
+        .await?
+        .into_iter()
+        .map(|stats| (stats.list_id.clone(), stats))
+        .collect();
+    let last_snapshots = state.store.get_snapshots(&account_pk, true).await?;
+
+    let html = maud::html! {
+        h2 { "Your lists" }
+        p {
+            "Lists whose title contains a filter such as "
+            code { "#mutuals" }
+            " are managed by the bot. Check that the filter below matches what you intended."
+        }
+
+        table {
+            thead {
+                tr {
+                    th { "List" }
+                    th { "Filter" }
+                    th { "Members" }
+                    th { "Last sync" }
+                }
+            }
+            tbody {
+                @for list in &lists {
+                    @let stats = stats.get(&list.id);
+                    tr {
+                        td { (list.title) }
+                        td {
+                            @match ListManagerTerms::from_title(&list.title) {
+                                None => { small { "not managed" } }
+                                Some(Ok(terms)) => { (terms.describe()) }
+                                Some(Err(e)) => {
+                                    span."pico-color-red-500" { "Invalid filter: "(e) }
+                                }
+                            }
+                        }
+                        td {
+                            @if let Some(stats) = stats {
+                                (stats.member_count)
+                            }
+                        }
+                        td {
+                            @if let Some(stats) = stats {
+                                "+"(stats.added)" / -"(stats.removed)
+                                br;
+                                small { (stats.synced_at) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        @if let Some(first) = last_snapshots.first() {
+            h3 { "Undo the last sync" }
+            p {
+                "The sync at "(first.sync_started_at)" changed "
+                @for (i, snapshot) in last_snapshots.iter().enumerate() {
+                    @if i > 0 { ", " }
+                    em { (snapshot.list_title) }
+                }
+                ". Reverting puts back the members these lists had before. Accounts you no longer follow can't be added back. If the filter is still the same, the next sync will make the same changes again."
+            }
+            form
+            method="post"
+            action="/account/revert-sync"
+            target="_blank"
+            data-hx-post="/account/revert-sync"
+            data-hx-swap="innerHTML"
+            data-hx-target="#sync-result"
+            data-hx-disabled-elt="#revert-sync"
+            data-hx-headers=(csrf.htmx_headers()) {
+                (csrf.field())
+                input.secondary id="revert-sync" type="submit" value="Revert last sync";
+                p id="sync-result";
+            }
+            script { (PreEscaped(SYNC_PROGRESS_SCRIPT)) }
+        }
+
+        h3 id="filter-builder" { "Create or rename a list" }
+        p { "Compose a filter, preview which accounts it matches, then save it as a list title." }
+        (filter_builder::render_form(&lists, &csrf))
+
+        a href="/account/admin" { "Back" }
+        script src="/htmx.js" {}
+    };
+
+    Ok(Html(with_site_chrome(html).into_string()).into_response())
+}
+
+async fn account_revert_sync(
+    State(state): State<AppState>,
+    login: LoggedIn,
+) -> Result<Response, ResponseError> {
+    let account_pk = login.account()?;
+    let body = state.store.revert_last_sync(&account_pk).await?;
+
+    let html: maud::Markup = match body {
+        SyncImmediateResult::Ok => maud::html! {
+            p { "Reverted the last sync. Refresh the page to see the lists." }
+        },
+        SyncImmediateResult::Error { code, value } => {
+            let (summary, action) = describe_sync_error(code);
+            maud::html! {
+                p.red { "Error: "(summary)" "(action) }
+                details {
+                    summary { "Details" }
+                    code { (value) }
+                }
+            }
+        }
+        SyncImmediateResult::Pending => maud::html! {
+            p data-sync-progress="/account/revert-sync" { "Revert ongoing." }
+        },
+        SyncImmediateResult::TooMany | SyncImmediateResult::NeedsReauth => {
+            unreachable!("reverts are not rate-limited and don't check reauth")
+        }
+    };
+
+    Ok(Html(html.into_string()).into_response())
+}
+
+/// Follows the progress of a pending sync, then fetches the result once it's done.
+const SYNC_PROGRESS_SCRIPT: &str = r##"
+document.body.addEventListener("htmx:afterSwap", function () {
+    var el = document.querySelector("[data-sync-progress]");
+    if (!el || el.dataset.listening) return;
+    el.dataset.listening = "1";
+    var source = new EventSource("/account/sync-progress");
+    source.addEventListener("progress", function (e) { el.textContent = e.data; });
+    source.addEventListener("finished", function () {
+        source.close();
+        htmx.ajax("POST", el.dataset.syncProgress || "/account/sync-immediate", "#sync-result");
+    });
+});
+"##;
+
 fn with_site_chrome(content: Markup) -> Markup {
     maud::html! {
         (maud::DOCTYPE)
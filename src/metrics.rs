@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+
+/// Process-wide metrics, exposed by the server under `/metrics`.
+pub struct Metrics {
+    registry: Registry,
+    pub syncs: IntCounterVec,
+    pub sync_duration: Histogram,
+    pub api_requests: IntCounterVec,
+    pub rate_limit_waits: IntCounterVec,
+    pub accounts: IntGaugeVec,
+    pub queue_depth: IntGauge,
+    pub list_members_added: IntCounter,
+    pub list_members_removed: IntCounter,
+    host_labels: Mutex<HashSet<String>>,
+}
+
+/// Hosts come from user input, so only this many get their own `host` label. Requests to any
+/// other host are counted under `other`.
+const MAX_HOST_LABELS: usize = 100;
+
+pub static METRICS: Lazy<Metrics> =
+    Lazy::new(|| Metrics::new().expect("failed to register metrics"));
+
+impl Metrics {
+    fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("list_bot".to_owned()), None)?;
+
+        let syncs = IntCounterVec::new(
+            Opts::new(
+                "syncs_total",
+                "Account syncs, by outcome (ok or error code)",
+            ),
+            &["outcome"],
+        )?;
+        let sync_duration = Histogram::with_opts(
+            HistogramOpts::new("sync_duration_seconds", "Time taken to sync one account").buckets(
+                vec![1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0],
+            ),
+        )?;
+        let api_requests = IntCounterVec::new(
+            Opts::new(
+                "api_requests_total",
+                "Requests to Mastodon instances, by host and HTTP status",
+            ),
+            &["host", "status"],
+        )?;
+        let rate_limit_waits = IntCounterVec::new(
+            Opts::new(
+                "rate_limit_waits_total",
+                "Times we backed off because an instance rate-limited us",
+            ),
+            &["host"],
+        )?;
+        let accounts = IntGaugeVec::new(
+            Opts::new("accounts", "Registered accounts, by state"),
+            &["state"],
+        )?;
+        let queue_depth = IntGauge::new("sync_queue_depth", "Accounts that are due for a sync")?;
+        let list_members_added = IntCounter::new(
+            "list_members_added_total",
+            "Accounts added to managed lists",
+        )?;
+        let list_members_removed = IntCounter::new(
+            "list_members_removed_total",
+            "Accounts removed from managed lists",
+        )?;
+
+        registry.register(Box::new(syncs.clone()))?;
+        registry.register(Box::new(sync_duration.clone()))?;
+        registry.register(Box::new(api_requests.clone()))?;
+        registry.register(Box::new(rate_limit_waits.clone()))?;
+        registry.register(Box::new(accounts.clone()))?;
+        registry.register(Box::new(queue_depth.clone()))?;
+        registry.register(Box::new(list_members_added.clone()))?;
+        registry.register(Box::new(list_members_removed.clone()))?;
+
+        Ok(Metrics {
+            registry,
+            syncs,
+            sync_duration,
+            api_requests,
+            rate_limit_waits,
+            accounts,
+            queue_depth,
+            list_members_added,
+            list_members_removed,
+            host_labels: Mutex::default(),
+        })
+    }
+
+    /// The `host` label for requests to `host`, see `MAX_HOST_LABELS`.
+    pub fn host_label<'a>(&self, host: &'a str) -> &'a str {
+        let mut host_labels = self.host_labels.lock().unwrap();
+        if host_labels.contains(host) {
+            host
+        } else if host_labels.len() < MAX_HOST_LABELS {
+            host_labels.insert(host.to_owned());
+            host
+        } else {
+            "other"
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("prometheus output is not utf-8"))
+    }
+}
+
+#[test]
+fn encode_metrics() {
+    // not the global METRICS, which other tests increment concurrently
+    let metrics = Metrics::new().unwrap();
+    metrics.syncs.with_label_values(&["ok"]).inc();
+    metrics
+        .api_requests
+        .with_label_values(&["mastodon.social", "200"])
+        .inc();
+
+    for i in 0..MAX_HOST_LABELS {
+        metrics.host_label(&format!("{}.example.com", i));
+    }
+    assert_eq!(metrics.host_label("0.example.com"), "0.example.com");
+    assert_eq!(metrics.host_label("mastodon.example"), "other");
+
+    let output = metrics.encode().unwrap();
+    assert!(output.contains("list_bot_syncs_total{outcome=\"ok\"} 1"));
+    assert!(
+        output.contains("list_bot_api_requests_total{host=\"mastodon.social\",status=\"200\"} 1")
+    );
+}
@@ -0,0 +1,93 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+/// What a running sync is currently doing, for showing progress to the user.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+pub enum SyncProgress {
+    Starting,
+    WaitingForHost,
+    FetchingLists,
+    FetchingFollows {
+        page: usize,
+        count: usize,
+    },
+    FetchingRelationships {
+        done: usize,
+        total: usize,
+    },
+    SyncingList {
+        index: usize,
+        total: usize,
+        title: String,
+    },
+    ListDone {
+        title: String,
+        added: usize,
+        removed: usize,
+    },
+    RateLimited {
+        wait_secs: u64,
+    },
+    Finished,
+}
+
+impl SyncProgress {
+    pub fn describe(&self) -> String {
+        match self {
+            SyncProgress::Starting => "Starting sync...".to_owned(),
+            SyncProgress::WaitingForHost => {
+                "Waiting for other syncs on your instance to finish...".to_owned()
+            }
+            SyncProgress::FetchingLists => "Fetching your lists...".to_owned(),
+            SyncProgress::FetchingFollows { page, count } => {
+                format!(
+                    "Fetching follows, page {} ({} accounts so far)...",
+                    page, count
+                )
+            }
+            SyncProgress::FetchingRelationships { done, total } => {
+                format!("Fetching relationships ({}/{})...", done, total)
+            }
+            SyncProgress::SyncingList {
+                index,
+                total,
+                title,
+            } => format!("Syncing list {} of {}: {}", index, total, title),
+            SyncProgress::ListDone {
+                title,
+                added,
+                removed,
+            } => format!("{}: added {}, removed {}", title, added, removed),
+            SyncProgress::RateLimited { wait_secs } => format!(
+                "Rate-limited by your instance, waiting {} seconds...",
+                wait_secs
+            ),
+            SyncProgress::Finished => "Finished.".to_owned(),
+        }
+    }
+}
+
+/// Sends progress events to whoever is watching. Does nothing if nobody is.
+#[derive(Clone, Default)]
+pub struct ProgressReporter(Option<Arc<watch::Sender<SyncProgress>>>);
+
+impl ProgressReporter {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(SyncProgress::Starting);
+        ProgressReporter(Some(Arc::new(sender)))
+    }
+
+    pub fn report(&self, progress: SyncProgress) {
+        if let Some(ref sender) = self.0 {
+            sender.send_replace(progress);
+        }
+    }
+
+    pub fn subscribe(&self) -> Option<watch::Receiver<SyncProgress>> {
+        self.0.as_ref().map(|sender| sender.subscribe())
+    }
+}
@@ -1,54 +1,396 @@
-use anyhow::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+use serde::Serialize;
+use tokio::sync::SemaphorePermit;
 
 use crate::api_cache::ApiCache;
-use crate::api_client::ApiClient;
+use crate::api_client::{ApiClient, SyncContext};
 use crate::api_helpers::get_next_link;
-use crate::api_models::List;
-use crate::list_manager::ListManager;
+use crate::api_models::{Account, List};
+use crate::config::AccountsFile;
+use crate::error::SyncError;
+use crate::hosts::{HostCoordinator, HostState};
+use crate::list_manager::{ListManager, ListManagerTerms, ListSyncStats, ReplaceOptions};
+use crate::progress::{ProgressReporter, SyncProgress};
+use crate::safety::SafetyLimits;
+use crate::snapshot::SnapshotTarget;
+
+/// How long a sync waits for other syncs against the same instance before giving up.
+const SYNC_SLOT_TIMEOUT: Duration = Duration::from_secs(30 * 60);
 
+#[derive(Default, Serialize)]
 pub struct RunStats {
+    /// Number of managed lists on the account.
     pub list_count: usize,
+    pub lists: Vec<ListSyncStats>,
+    /// Lists that could not be synced. The other lists are still synced.
+    pub failed_lists: Vec<FailedList>,
+    /// Set if the run was stopped before all lists were synced.
+    pub interrupted: bool,
+    /// Requests sent to the instance, including retries.
+    pub api_calls: usize,
+    /// Number of accounts the user follows, if there were lists to sync.
+    pub follows_count: Option<usize>,
 }
 
-pub async fn run_once(host: &str, token: &str) -> Result<RunStats, Error> {
-    let api_client = ApiClient::new(host, Some(token))?;
+/// Machine-readable result of `run-once --output json`.
+#[derive(Serialize)]
+pub struct RunReport {
+    pub host: String,
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    #[serde(flatten)]
+    pub stats: RunStats,
+    pub error: Option<RunError>,
+}
 
-    tracing::info!("fetching all your lists");
+#[derive(Serialize)]
+pub struct RunError {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl From<&SyncError> for RunError {
+    fn from(e: &SyncError) -> Self {
+        RunError {
+            code: e.code(),
+            message: e.to_string(),
+        }
+    }
+}
 
+#[derive(Serialize)]
+pub struct FailedList {
+    pub list_id: String,
+    pub title: String,
+    pub error: RunError,
+}
+
+#[derive(Clone, Default)]
+pub struct RunOptions {
+    pub progress: ProgressReporter,
+    /// Checked between lists. Once set, the run stops after the current list.
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Where list memberships are saved before they are changed.
+    pub snapshots: SnapshotTarget,
+    pub limits: SafetyLimits,
+    /// Sync lists against the members recorded at the last sync, see `ListRecord`.
+    pub incremental: bool,
+    /// Shared with other runs, so that accounts on the same instance don't sync at the same time.
+    pub hosts: HostCoordinator,
+}
+
+impl RunOptions {
+    fn should_stop(&self) -> bool {
+        self.stop
+            .as_ref()
+            .is_some_and(|stop| stop.load(Ordering::Relaxed))
+    }
+}
+
+pub async fn get_lists(api_client: &ApiClient) -> Result<Vec<List>, SyncError> {
     let mut url_opt = Some("/api/v1/lists".to_owned());
 
-    let mut list_managers = Vec::new();
+    let mut result = Vec::new();
 
     while let Some(url) = url_opt.clone() {
         let res = api_client
             .get(&url, Box::new(|builder| builder))
-            .await?
-            .error_for_status()?;
+            .await
+            .sync_context("failed to get lists")?
+            .error_for_status()
+            .sync_context("failed to get lists")?;
 
         let next_url = get_next_link(&res);
 
-        let lists: Vec<List> = res.json().await?;
+        let lists: Vec<List> = res.json().await.sync_context("failed to parse lists")?;
+        result.extend(lists);
 
-        for list in lists {
-            if let Some(manager) = ListManager::parse(list) {
-                list_managers.push(manager);
+        url_opt = next_url;
+    }
+
+    Ok(result)
+}
+
+pub async fn run_once(
+    host: &str,
+    token: &str,
+    options: &RunOptions,
+) -> Result<RunStats, SyncError> {
+    let host_state = options.hosts.host(host)?;
+    let _slot = wait_for_sync_slot(&host_state, &options.progress).await?;
+    let api_client = ApiClient::with_host(host_state.clone(), Some(token))
+        .with_progress(options.progress.clone());
+    let mut stats = RunStats::default();
+    let result = sync_lists(&api_client, options, &mut stats).await;
+    stats.api_calls = api_client.request_count();
+    result.map(|()| stats)
+}
+
+/// Wait for other syncs against the same instance to finish, for at most `SYNC_SLOT_TIMEOUT`.
+pub async fn wait_for_sync_slot<'a>(
+    host_state: &'a HostState,
+    progress: &ProgressReporter,
+) -> Result<SemaphorePermit<'a>, SyncError> {
+    if host_state.is_busy() {
+        tracing::info!("[{}] waiting for another sync to finish", host_state.host);
+        progress.report(SyncProgress::WaitingForHost);
+    }
+    tokio::time::timeout(SYNC_SLOT_TIMEOUT, host_state.sync_slot())
+        .await
+        .map_err(|_| {
+            SyncError::HostBusy(format!(
+                "other syncs against {} did not finish within {} minutes",
+                host_state.host,
+                SYNC_SLOT_TIMEOUT.as_secs() / 60
+            ))
+        })
+}
+
+/// Like `run_once`, but also reports the lists that were synced before an error occurred.
+pub async fn run_once_report(host: &str, token: &str, options: &RunOptions) -> RunReport {
+    let started_at = Utc::now().naive_utc();
+    let mut stats = RunStats::default();
+
+    let result = match options.hosts.host(host) {
+        Ok(host_state) => match wait_for_sync_slot(&host_state, &options.progress).await {
+            Ok(_slot) => {
+                let api_client = ApiClient::with_host(host_state.clone(), Some(token))
+                    .with_progress(options.progress.clone());
+                let result = sync_lists(&api_client, options, &mut stats).await;
+                stats.api_calls = api_client.request_count();
+                result
             }
-        }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e.into()),
+    };
 
-        url_opt = next_url;
+    RunReport {
+        host: host.to_owned(),
+        started_at,
+        finished_at: Utc::now().naive_utc(),
+        stats,
+        error: result.err().as_ref().map(RunError::from),
     }
+}
+
+async fn sync_lists(
+    api_client: &ApiClient,
+    options: &RunOptions,
+    stats: &mut RunStats,
+) -> Result<(), SyncError> {
+    tracing::info!("fetching all your lists");
+    api_client.progress.report(SyncProgress::FetchingLists);
+
+    let mut list_managers = get_lists(api_client)
+        .await?
+        .into_iter()
+        .filter_map(ListManager::parse)
+        .collect::<Vec<_>>();
 
+    stats.list_count = list_managers.len();
     if list_managers.is_empty() {
-        return Ok(RunStats { list_count: 0 });
+        return Ok(());
     }
 
     let mut api_cache = ApiCache::default();
+    let snapshots = options.snapshots.writer();
+    let total = list_managers.len();
+
+    let follows_count = api_cache.get_follows(api_client).await?.len();
+    let credentials = api_cache.get_credentials(api_client).await?;
+    let handle = format!("{}@{}", credentials.username, api_client.host);
+    let previous_follows_count = options.snapshots.previous_follows_count(&handle).await?;
+    options
+        .limits
+        .check_follows(previous_follows_count, follows_count)
+        .map_err(SyncError::SafetyLimit)?;
+    stats.follows_count = Some(follows_count);
+
+    // a list that fails is skipped, but the sync still fails in the end
+    let mut first_error = None;
+
+    for (index, manager) in list_managers.iter_mut().enumerate() {
+        if options.should_stop() {
+            tracing::info!("stopping after {} of {} lists", index, total);
+            stats.interrupted = true;
+            break;
+        }
+
+        api_client.progress.report(SyncProgress::SyncingList {
+            index: index + 1,
+            total,
+            title: manager.title().to_owned(),
+        });
+
+        let replace_options = ReplaceOptions {
+            limits: &options.limits,
+            incremental: options.incremental,
+        };
+        match manager
+            .sync_list(api_client, &mut api_cache, &snapshots, &replace_options)
+            .await
+        {
+            Ok(list_stats) => stats.lists.push(list_stats),
+            // the other lists would fail the same way
+            Err(
+                e @ (SyncError::Auth(_)
+                | SyncError::RateLimited(_)
+                | SyncError::InstanceUnreachable(_)),
+            ) => return Err(e),
+            Err(e) => {
+                tracing::warn!("skipping list {}: {}", manager.title(), e);
+                stats.failed_lists.push(FailedList {
+                    list_id: manager.list_id().to_owned(),
+                    title: manager.title().to_owned(),
+                    error: RunError::from(&e),
+                });
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    options
+        .snapshots
+        .save_follows_count(&handle, follows_count)
+        .await?;
+
+    Ok(())
+}
+
+/// Sync every account in the config file, one after another, and print a summary line per
+/// account. Returns the number of accounts that failed.
+pub async fn run_accounts(config: &AccountsFile, options: &RunOptions) -> usize {
+    let mut failures = 0;
+
+    for account in &config.accounts {
+        let result = match account.token() {
+            Ok(token) => run_once(&account.host, &token, options).await,
+            Err(e) => Err(SyncError::Internal(e.to_string())),
+        };
+
+        match result {
+            Ok(stats) => {
+                let added: usize = stats.lists.iter().map(|list| list.added).sum();
+                let removed: usize = stats.lists.iter().map(|list| list.removed).sum();
+                println!(
+                    "{}: synced {} lists, added {}, removed {}",
+                    account.name(),
+                    stats.list_count,
+                    added,
+                    removed
+                );
+            }
+            Err(e) => {
+                tracing::error!("failed to sync {}: {}", account.name(), e);
+                println!("{}: failed ({}): {}", account.name(), e.code(), e);
+                failures += 1;
+            }
+        }
+    }
+
+    failures
+}
+
+/// Print why an account is or isn't a member of a managed list, term by term. Doesn't change the
+/// list.
+pub async fn explain(
+    hosts: &HostCoordinator,
+    host: &str,
+    token: &str,
+    list_id: &str,
+    acct: &str,
+) -> Result<(), SyncError> {
+    let api_client = hosts.client(host, Some(token))?;
+
+    let res = api_client
+        .get(
+            &format!("/api/v1/lists/{}", list_id),
+            Box::new(|builder| builder),
+        )
+        .await
+        .sync_context("failed to get list")?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(SyncError::ListNotFound {
+            list_id: list_id.to_owned(),
+        });
+    }
+    let list: List = res
+        .error_for_status()
+        .sync_context("failed to get list")?
+        .json()
+        .await
+        .sync_context("failed to parse list")?;
+
+    let terms = match ListManagerTerms::from_title(&list.title) {
+        Some(Ok(terms)) => terms,
+        Some(Err(e)) => {
+            return Err(SyncError::Parse(format!(
+                "list {:?} has an invalid filter: {}",
+                list.title, e
+            )))
+        }
+        None => {
+            return Err(SyncError::Parse(format!(
+                "list {:?} has no filter and is not managed",
+                list.title
+            )))
+        }
+    };
+
+    let acct = acct.strip_prefix('@').unwrap_or(acct).to_owned();
+    let lookup_acct = acct.clone();
+    let res = api_client
+        .get(
+            "/api/v1/accounts/lookup",
+            Box::new(move |builder| builder.query(&[("acct", lookup_acct.as_str())])),
+        )
+        .await
+        .sync_context("failed to look up account")?;
+    if res.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(SyncError::AccountNotFound { acct });
+    }
+    let account: Account = res
+        .error_for_status()
+        .sync_context("failed to look up account")?
+        .json()
+        .await
+        .sync_context("failed to parse account")?;
+
+    println!("list: {} ({})", list.title, terms.describe());
+    println!("account: {} (id {})", account.acct, account.id);
+
+    let mut api_cache = ApiCache::default();
+    let Some(verdicts) = terms
+        .explain(&api_client, &mut api_cache, &account.id)
+        .await?
+    else {
+        println!("result: not a member, you don't follow this account");
+        return Ok(());
+    };
+
+    for verdict in &verdicts {
+        println!(
+            "  {} {}: {}",
+            if verdict.matches { "yes" } else { "no " },
+            verdict.term,
+            verdict.detail
+        );
+    }
 
-    for manager in &mut list_managers {
-        manager.sync_list(&api_client, &mut api_cache).await?;
+    if verdicts.iter().all(|verdict| verdict.matches) {
+        println!("result: member, all terms match");
+    } else {
+        println!("result: not a member");
     }
 
-    Ok(RunStats {
-        list_count: list_managers.len(),
-    })
+    Ok(())
 }
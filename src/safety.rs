@@ -0,0 +1,93 @@
+/// Lists with fewer members are not checked, small lists legitimately change a lot.
+const MIN_LIST_SIZE_CHECKED: usize = 10;
+/// Same for accounts that follow only a few others.
+const MIN_FOLLOWS_CHECKED: usize = 10;
+
+/// Guards against a sync wiping out lists because of a bug or a misbehaving server, e.g. one
+/// that suddenly returns no follows.
+#[derive(Debug, Clone)]
+pub struct SafetyLimits {
+    /// Refuse to remove more than this fraction of a list's members in one sync.
+    pub max_removed_fraction: f64,
+    /// Refuse to sync if the number of follows dropped by more than this fraction since the last
+    /// successful sync.
+    pub max_follows_drop: f64,
+    /// Ignore the limits.
+    pub force: bool,
+}
+
+impl Default for SafetyLimits {
+    fn default() -> Self {
+        SafetyLimits {
+            max_removed_fraction: 0.5,
+            max_follows_drop: 0.5,
+            force: false,
+        }
+    }
+}
+
+impl SafetyLimits {
+    pub fn forced() -> Self {
+        SafetyLimits {
+            force: true,
+            ..SafetyLimits::default()
+        }
+    }
+
+    pub fn check_removals(&self, member_count: usize, removed: usize) -> Result<(), String> {
+        if self.force || member_count < MIN_LIST_SIZE_CHECKED {
+            return Ok(());
+        }
+
+        let fraction = removed as f64 / member_count as f64;
+        if fraction > self.max_removed_fraction {
+            return Err(format!(
+                "the sync would remove {} of {} members ({:.0}%), more than the limit of {:.0}%",
+                removed,
+                member_count,
+                fraction * 100.0,
+                self.max_removed_fraction * 100.0
+            ));
+        }
+
+        Ok(())
+    }
+
+    pub fn check_follows(&self, previous: Option<usize>, current: usize) -> Result<(), String> {
+        let Some(previous) = previous else {
+            return Ok(());
+        };
+        if self.force || previous < MIN_FOLLOWS_CHECKED || current >= previous {
+            return Ok(());
+        }
+
+        let drop = (previous - current) as f64 / previous as f64;
+        if drop > self.max_follows_drop {
+            return Err(format!(
+                "you follow {} accounts, down from {} at the last sync ({:.0}% fewer), more than the limit of {:.0}%",
+                current,
+                previous,
+                drop * 100.0,
+                self.max_follows_drop * 100.0
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[test]
+fn limits() {
+    let limits = SafetyLimits::default();
+    assert!(limits.check_removals(100, 50).is_ok());
+    assert!(limits.check_removals(100, 51).is_err());
+    assert!(limits.check_removals(5, 5).is_ok());
+    assert!(SafetyLimits::forced().check_removals(100, 100).is_ok());
+
+    assert!(limits.check_follows(None, 0).is_ok());
+    assert!(limits.check_follows(Some(100), 200).is_ok());
+    assert!(limits.check_follows(Some(100), 50).is_ok());
+    assert!(limits.check_follows(Some(100), 0).is_err());
+    assert!(limits.check_follows(Some(5), 0).is_ok());
+    assert!(SafetyLimits::forced().check_follows(Some(100), 0).is_ok());
+}
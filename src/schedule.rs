@@ -0,0 +1,344 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{Datelike, Duration, NaiveDateTime, Timelike};
+
+/// No matter what the user configures, we don't sync more often than this to protect instances.
+pub const MIN_SYNC_INTERVAL: Duration = Duration::hours(6);
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SyncFrequency {
+    EverySixHours,
+    Daily,
+    Weekly,
+    Cron(CronSchedule),
+}
+
+impl FromStr for SyncFrequency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "6h" => Ok(SyncFrequency::EverySixHours),
+            "daily" => Ok(SyncFrequency::Daily),
+            "weekly" => Ok(SyncFrequency::Weekly),
+            s => match s.strip_prefix("cron:") {
+                Some(expr) => Ok(SyncFrequency::Cron(expr.parse()?)),
+                None => Err(format!("unknown sync frequency {:?}", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for SyncFrequency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SyncFrequency::EverySixHours => write!(f, "6h"),
+            SyncFrequency::Daily => write!(f, "daily"),
+            SyncFrequency::Weekly => write!(f, "weekly"),
+            SyncFrequency::Cron(cron) => write!(f, "cron:{}", cron),
+        }
+    }
+}
+
+/// When an account should be synced. All times are UTC.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Schedule {
+    pub frequency: SyncFrequency,
+    /// Hour of the day to prefer for daily and weekly syncs.
+    pub preferred_hour: Option<u32>,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule {
+            frequency: SyncFrequency::Daily,
+            preferred_hour: None,
+        }
+    }
+}
+
+impl Schedule {
+    pub fn new(frequency: SyncFrequency, preferred_hour: Option<u32>) -> Result<Self, String> {
+        if let Some(hour) = preferred_hour {
+            if hour > 23 {
+                return Err(format!("invalid hour {}", hour));
+            }
+        }
+
+        Ok(Schedule {
+            frequency,
+            preferred_hour,
+        })
+    }
+
+    /// The next time to sync, given the time of the last sync.
+    pub fn next_after(&self, last: NaiveDateTime) -> NaiveDateTime {
+        let earliest = last + MIN_SYNC_INTERVAL;
+
+        let interval = match &self.frequency {
+            SyncFrequency::EverySixHours => return earliest,
+            SyncFrequency::Daily => Duration::days(1),
+            SyncFrequency::Weekly => Duration::weeks(1),
+            SyncFrequency::Cron(cron) => return cron.next_after(earliest),
+        };
+
+        let Some(hour) = self.preferred_hour else {
+            return last + interval;
+        };
+
+        // allow shifting the sync by up to half a day in either direction to hit the preferred
+        // hour
+        let start = (last + interval - Duration::hours(12)).max(earliest);
+        let mut candidate = start
+            .date()
+            .and_hms_opt(hour, 0, 0)
+            .expect("preferred hour was validated");
+        if candidate < start {
+            candidate += Duration::days(1);
+        }
+        candidate
+    }
+
+    pub fn describe(&self) -> String {
+        let frequency = match &self.frequency {
+            SyncFrequency::EverySixHours => return "every 6 hours".to_owned(),
+            SyncFrequency::Daily => "once per day",
+            SyncFrequency::Weekly => "once per week",
+            SyncFrequency::Cron(cron) => return format!("according to `{}` (UTC)", cron),
+        };
+
+        match self.preferred_hour {
+            Some(hour) => format!("{} around {:02}:00 UTC", frequency, hour),
+            None => frequency.to_owned(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+struct CronField {
+    // bit n is set if value n matches
+    values: u64,
+    is_star: bool,
+}
+
+impl CronField {
+    fn matches(&self, value: u32) -> bool {
+        self.values & (1 << value) != 0
+    }
+}
+
+/// A five-field cron expression (minute, hour, day of month, month, day of week), supporting `*`,
+/// numbers, ranges, lists and steps.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct CronSchedule {
+    source: String,
+    minute: CronField,
+    hour: CronField,
+    day_of_month: CronField,
+    month: CronField,
+    day_of_week: CronField,
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use pom::parser::*;
+
+        // (start, end, step), end inclusive. None for start/end means `*`.
+        type Item = (Option<(u32, Option<u32>)>, Option<u32>);
+
+        let number = || {
+            one_of(b"0123456789")
+                .repeat(1..3)
+                .map(|digits| String::from_utf8(digits).unwrap().parse::<u32>().unwrap())
+        };
+        let item = || {
+            let range =
+                sym(b'*').map(|_| None) | (number() + (sym(b'-') * number()).opt()).map(Some);
+            range + (sym(b'/') * number()).opt()
+        };
+        let field = || list(item(), sym(b','));
+        let whitespace = || sym(b' ').repeat(1..).discard();
+        let parser = sym(b' ').repeat(0..).discard() * field()
+            + (whitespace() * field())
+            + (whitespace() * field())
+            + (whitespace() * field())
+            + (whitespace() * field())
+            - sym(b' ').repeat(0..).discard()
+            - end();
+
+        let ((((minute, hour), day_of_month), month), day_of_week) = parser
+            .parse(s.as_bytes())
+            .map_err(|e| format!("invalid cron expression: {}", e))?;
+
+        let build = |name: &str, items: Vec<Item>, min: u32, max: u32| {
+            let mut field = CronField {
+                values: 0,
+                is_star: false,
+            };
+
+            for (range, step) in items {
+                let (start, end) = match range {
+                    None => {
+                        field.is_star |= step.is_none();
+                        (min, max)
+                    }
+                    Some((start, None)) if step.is_some() => (start, max),
+                    Some((start, None)) => (start, start),
+                    Some((start, Some(end))) => (start, end),
+                };
+
+                if start < min || end > max || start > end {
+                    return Err(format!("{} must be between {} and {}", name, min, max));
+                }
+
+                let step = step.unwrap_or(1);
+                if step == 0 {
+                    return Err(format!("{} step must not be zero", name));
+                }
+
+                for value in (start..=end).step_by(step as usize) {
+                    field.values |= 1 << value;
+                }
+            }
+
+            Ok(field)
+        };
+
+        let mut day_of_week = build("day of week", day_of_week, 0, 7)?;
+        // both 0 and 7 are sunday
+        if day_of_week.matches(7) {
+            day_of_week.values |= 1;
+        }
+
+        Ok(CronSchedule {
+            source: s.trim().to_owned(),
+            minute: build("minute", minute, 0, 59)?,
+            hour: build("hour", hour, 0, 23)?,
+            day_of_month: build("day of month", day_of_month, 1, 31)?,
+            month: build("month", month, 1, 12)?,
+            day_of_week,
+        })
+    }
+}
+
+impl CronSchedule {
+    fn day_matches(&self, time: NaiveDateTime) -> bool {
+        let dom = self.day_of_month.matches(time.day());
+        let dow = self
+            .day_of_week
+            .matches(time.weekday().num_days_from_sunday());
+
+        // like in vixie cron, if both fields are restricted, either of them may match
+        if self.day_of_month.is_star || self.day_of_week.is_star {
+            dom && dow
+        } else {
+            dom || dow
+        }
+    }
+
+    /// The first matching minute at or after `start`.
+    pub fn next_after(&self, start: NaiveDateTime) -> NaiveDateTime {
+        let mut time = start
+            .with_second(0)
+            .and_then(|t| t.with_nanosecond(0))
+            .unwrap();
+        if time < start {
+            time += Duration::minutes(1);
+        }
+
+        // every valid expression matches at least once within a few years (e.g. feb 29)
+        let limit = time + Duration::days(366 * 5);
+
+        while time < limit {
+            if !self.month.matches(time.month()) || !self.day_matches(time) {
+                time = time
+                    .date()
+                    .succ_opt()
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0)
+                    .unwrap();
+                continue;
+            }
+
+            if !self.hour.matches(time.hour()) {
+                time = time.with_minute(0).unwrap() + Duration::hours(1);
+                continue;
+            }
+
+            if !self.minute.matches(time.minute()) {
+                time += Duration::minutes(1);
+                continue;
+            }
+
+            return time;
+        }
+
+        // impossible dates such as "0 0 31 2 *" never match, fall back to the minimum interval
+        start
+    }
+}
+
+#[cfg(test)]
+fn dt(s: &str) -> NaiveDateTime {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M").unwrap()
+}
+
+#[test]
+fn parse_frequency() {
+    assert_eq!("daily".parse(), Ok(SyncFrequency::Daily));
+    assert_eq!("6h".parse(), Ok(SyncFrequency::EverySixHours));
+    assert!("hourly".parse::<SyncFrequency>().is_err());
+    assert!("cron:* * *".parse::<SyncFrequency>().is_err());
+    assert!("cron:60 * * * *".parse::<SyncFrequency>().is_err());
+    assert_eq!(
+        "cron:0 3 * * 1"
+            .parse::<SyncFrequency>()
+            .unwrap()
+            .to_string(),
+        "cron:0 3 * * 1"
+    );
+}
+
+#[test]
+fn next_after() {
+    let last = dt("2024-01-01 10:30");
+
+    let schedule = Schedule::new(SyncFrequency::Daily, None).unwrap();
+    assert_eq!(schedule.next_after(last), dt("2024-01-02 10:30"));
+
+    let schedule = Schedule::new(SyncFrequency::Daily, Some(4)).unwrap();
+    assert_eq!(schedule.next_after(last), dt("2024-01-02 04:00"));
+
+    let schedule = Schedule::new(SyncFrequency::Daily, Some(20)).unwrap();
+    assert_eq!(schedule.next_after(last), dt("2024-01-02 20:00"));
+
+    let schedule = Schedule::new(SyncFrequency::EverySixHours, Some(20)).unwrap();
+    assert_eq!(schedule.next_after(last), dt("2024-01-01 16:30"));
+
+    let schedule = Schedule::new(SyncFrequency::Weekly, Some(4)).unwrap();
+    assert_eq!(schedule.next_after(last), dt("2024-01-08 04:00"));
+}
+
+#[test]
+fn next_after_cron() {
+    let last = dt("2024-01-01 10:30");
+
+    let cron =
+        |expr: &str| Schedule::new(SyncFrequency::Cron(expr.parse().unwrap()), None).unwrap();
+
+    // mondays at 3am, 2024-01-01 is a monday
+    assert_eq!(cron("0 3 * * 1").next_after(last), dt("2024-01-08 03:00"));
+    // every minute is clamped to the minimum interval
+    assert_eq!(cron("* * * * *").next_after(last), dt("2024-01-01 16:30"));
+    assert_eq!(cron("0 */4 * * *").next_after(last), dt("2024-01-01 20:00"));
+    assert_eq!(cron("0 0 1 3 *").next_after(last), dt("2024-03-01 00:00"));
+}
@@ -0,0 +1,120 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{NaiveDateTime, Utc};
+
+use crate::store::Store;
+
+/// How often the scheduler looks for accounts that are due.
+const CYCLE_INTERVAL: Duration = Duration::from_secs(3600);
+/// How often the scheduler task records that it is alive, also while a cycle is running.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(60);
+/// The scheduler is considered stuck if it hasn't recorded a heartbeat for this long. Cycles
+/// themselves can take arbitrarily long, since syncs wait for rate limits and for each other.
+const STALE_AFTER: chrono::Duration = chrono::Duration::minutes(10);
+const RESTART_DELAY: Duration = Duration::from_secs(10);
+
+/// Shared between the scheduler task and the health endpoints.
+#[derive(Debug)]
+pub struct SchedulerStatus {
+    started_at: NaiveDateTime,
+    last_cycle_at: Mutex<Option<NaiveDateTime>>,
+    last_heartbeat_at: Mutex<Option<NaiveDateTime>>,
+    restarts: AtomicU64,
+}
+
+impl SchedulerStatus {
+    fn new() -> Self {
+        SchedulerStatus {
+            started_at: Utc::now().naive_utc(),
+            last_cycle_at: Mutex::new(None),
+            last_heartbeat_at: Mutex::new(None),
+            restarts: AtomicU64::new(0),
+        }
+    }
+
+    pub fn last_cycle_at(&self) -> Option<NaiveDateTime> {
+        *self.last_cycle_at.lock().unwrap()
+    }
+
+    pub fn restarts(&self) -> u64 {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    pub fn last_heartbeat_at(&self) -> Option<NaiveDateTime> {
+        *self.last_heartbeat_at.lock().unwrap()
+    }
+
+    /// Whether the scheduler task recorded a heartbeat recently, or was only just started.
+    pub fn is_healthy(&self, now: NaiveDateTime) -> bool {
+        let last = self.last_heartbeat_at().unwrap_or(self.started_at);
+        now - last < STALE_AFTER
+    }
+
+    fn heartbeat(&self) {
+        *self.last_heartbeat_at.lock().unwrap() = Some(Utc::now().naive_utc());
+    }
+}
+
+/// Start the background sync loop. If it panics, it is restarted after a short delay.
+pub fn spawn(store: Store) -> Arc<SchedulerStatus> {
+    let status = Arc::new(SchedulerStatus::new());
+    let supervisor_status = status.clone();
+
+    tokio::spawn(async move {
+        loop {
+            let result = tokio::spawn(run(store.clone(), supervisor_status.clone())).await;
+
+            match result {
+                Ok(()) => tracing::error!("scheduler task exited, restarting"),
+                Err(e) => tracing::error!("scheduler task died, restarting: {}", e),
+            }
+
+            supervisor_status.restarts.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(RESTART_DELAY).await;
+        }
+    });
+
+    status
+}
+
+async fn run(store: Store, status: Arc<SchedulerStatus>) {
+    loop {
+        match with_heartbeat(&status, store.sync_all_accounts()).await {
+            Ok((success, failure)) => {
+                tracing::info!("cronjob: {} success, {} failure", success, failure);
+                *status.last_cycle_at.lock().unwrap() = Some(Utc::now().naive_utc());
+            }
+            Err(e) => tracing::error!("failed to run cronjob: {:?}", e),
+        }
+
+        with_heartbeat(&status, tokio::time::sleep(CYCLE_INTERVAL)).await;
+    }
+}
+
+/// Run `future`, recording a heartbeat every `HEARTBEAT_INTERVAL` until it completes.
+async fn with_heartbeat<T>(status: &SchedulerStatus, future: impl Future<Output = T>) -> T {
+    tokio::pin!(future);
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            result = &mut future => return result,
+            _ = heartbeat.tick() => status.heartbeat(),
+        }
+    }
+}
+
+#[test]
+fn stale_scheduler() {
+    let status = SchedulerStatus::new();
+    let now = status.started_at;
+    assert!(status.is_healthy(now));
+    assert!(!status.is_healthy(now + chrono::Duration::hours(4)));
+
+    // a long cycle is fine as long as the task keeps beating
+    *status.last_heartbeat_at.lock().unwrap() = Some(now + chrono::Duration::minutes(235));
+    assert!(status.is_healthy(now + chrono::Duration::hours(4)));
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Error};
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::api_cache::ApiCache;
+use crate::api_client::ApiClient;
+use crate::api_models::List;
+use crate::error::SyncError;
+use crate::hosts::HostCoordinator;
+use crate::list_manager::{replace_members, ListMember, ReplaceOptions};
+use crate::safety::SafetyLimits;
+use crate::store::{AccountPk, Store};
+
+/// Older snapshots of the same list are deleted.
+pub const MAX_SNAPSHOTS_PER_LIST: usize = 10;
+/// Number of follows per account at the last successful sync, next to the snapshot files.
+const FOLLOWS_COUNTS_FILE: &str = "follows-counts.json";
+/// Subdirectory for `ListRecord`s.
+const RECORDS_DIR: &str = "records";
+
+/// Membership of a list right before a sync changed it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListSnapshot {
+    pub host: String,
+    pub list_id: String,
+    pub list_title: String,
+    /// When the sync that changed the list started. Shared by all lists changed in one sync.
+    pub sync_started_at: NaiveDateTime,
+    pub members: Vec<ListMember>,
+}
+
+/// Members of a list as of the end of the last sync, so that incremental syncs don't have to
+/// fetch them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ListRecord {
+    pub list_id: String,
+    pub list_title: String,
+    /// When the members were last fetched from the instance rather than taken from the record.
+    pub verified_at: NaiveDateTime,
+    pub members: Vec<ListMember>,
+}
+
+/// Where snapshots are saved: JSON files for the CLI, SQLite for the server. The number of
+/// follows at the last sync is kept there as well, for `SafetyLimits::check_follows`, and the
+/// `ListRecord` of each list.
+#[derive(Clone, Default)]
+pub enum SnapshotTarget {
+    #[default]
+    None,
+    Directory(PathBuf),
+    Database(Store, AccountPk),
+}
+
+impl SnapshotTarget {
+    /// Start a sync. Snapshots saved through the writer are grouped by its start time.
+    pub fn writer(&self) -> SnapshotWriter {
+        SnapshotWriter {
+            target: self.clone(),
+            sync_started_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// `account` is the `user@host` handle, since a directory may be shared by several accounts.
+    pub async fn previous_follows_count(&self, account: &str) -> Result<Option<usize>, SyncError> {
+        match self {
+            SnapshotTarget::None => Ok(None),
+            SnapshotTarget::Directory(dir) => Ok(read_follows_counts(dir)?.get(account).copied()),
+            SnapshotTarget::Database(store, account_pk) => Ok(store
+                .get_follows_count(account_pk)
+                .await
+                .map_err(|e| SyncError::Internal(format!("failed to get follows count: {}", e)))?),
+        }
+    }
+
+    pub async fn save_follows_count(&self, account: &str, count: usize) -> Result<(), SyncError> {
+        match self {
+            SnapshotTarget::None => Ok(()),
+            SnapshotTarget::Directory(dir) => {
+                let mut counts = read_follows_counts(dir)?;
+                counts.insert(account.to_owned(), count);
+                std::fs::create_dir_all(dir)
+                    .and_then(|()| {
+                        std::fs::write(
+                            dir.join(FOLLOWS_COUNTS_FILE),
+                            serde_json::to_vec_pretty(&counts)?,
+                        )
+                    })
+                    .map_err(|e| {
+                        SyncError::Internal(format!("failed to save follows count: {}", e))
+                    })
+            }
+            SnapshotTarget::Database(store, account_pk) => store
+                .set_follows_count(account_pk, count)
+                .await
+                .map_err(|e| SyncError::Internal(format!("failed to save follows count: {}", e))),
+        }
+    }
+}
+
+fn read_follows_counts(dir: &Path) -> Result<BTreeMap<String, usize>, SyncError> {
+    match std::fs::read(dir.join(FOLLOWS_COUNTS_FILE)) {
+        Ok(contents) => serde_json::from_slice(&contents)
+            .map_err(|e| SyncError::Internal(format!("failed to parse follows counts: {}", e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(SyncError::Internal(format!(
+            "failed to read follows counts: {}",
+            e
+        ))),
+    }
+}
+
+pub struct SnapshotWriter {
+    target: SnapshotTarget,
+    sync_started_at: NaiveDateTime,
+}
+
+impl SnapshotWriter {
+    pub async fn save(
+        &self,
+        host: &str,
+        list: &List,
+        members: Vec<ListMember>,
+    ) -> Result<(), SyncError> {
+        let snapshot = ListSnapshot {
+            host: host.to_owned(),
+            list_id: list.id.clone(),
+            list_title: list.title.clone(),
+            sync_started_at: self.sync_started_at,
+            members,
+        };
+
+        match &self.target {
+            SnapshotTarget::None => Ok(()),
+            SnapshotTarget::Directory(dir) => save_to_directory(dir, &snapshot)
+                .map_err(|e| SyncError::Internal(format!("failed to save snapshot: {}", e))),
+            SnapshotTarget::Database(store, account_pk) => store
+                .save_snapshot(account_pk, &snapshot)
+                .await
+                .map_err(|e| SyncError::Internal(format!("failed to save snapshot: {}", e))),
+        }
+    }
+
+    pub async fn load_record(
+        &self,
+        host: &str,
+        list_id: &str,
+    ) -> Result<Option<ListRecord>, SyncError> {
+        let result = match &self.target {
+            SnapshotTarget::None => return Ok(None),
+            SnapshotTarget::Directory(dir) => {
+                match std::fs::read(record_path(dir, host, list_id)) {
+                    Ok(contents) => serde_json::from_slice(&contents)
+                        .map(Some)
+                        .map_err(|e| e.to_string()),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                    Err(e) => Err(e.to_string()),
+                }
+            }
+            SnapshotTarget::Database(store, account_pk) => store
+                .get_list_record(account_pk, list_id)
+                .await
+                .map_err(|e| e.to_string()),
+        };
+
+        result.map_err(|e| SyncError::Internal(format!("failed to load list record: {}", e)))
+    }
+
+    pub async fn save_record(&self, host: &str, record: &ListRecord) -> Result<(), SyncError> {
+        let result = match &self.target {
+            SnapshotTarget::None => return Ok(()),
+            SnapshotTarget::Directory(dir) => {
+                let path = record_path(dir, host, &record.list_id);
+                std::fs::create_dir_all(dir.join(RECORDS_DIR))
+                    .and_then(|()| std::fs::write(path, serde_json::to_vec(record)?))
+                    .map_err(|e| e.to_string())
+            }
+            SnapshotTarget::Database(store, account_pk) => store
+                .save_list_record(account_pk, record)
+                .await
+                .map_err(|e| e.to_string()),
+        };
+
+        result.map_err(|e| SyncError::Internal(format!("failed to save list record: {}", e)))
+    }
+}
+
+fn record_path(dir: &Path, host: &str, list_id: &str) -> PathBuf {
+    dir.join(RECORDS_DIR)
+        .join(format!("{}-{}.json", host.replace(':', "_"), list_id))
+}
+
+fn save_to_directory(dir: &Path, snapshot: &ListSnapshot) -> Result<(), std::io::Error> {
+    std::fs::create_dir_all(dir)?;
+
+    let id = format!(
+        "{}-{}-{}",
+        snapshot.host.replace(':', "_"),
+        snapshot.list_id,
+        snapshot.sync_started_at.format("%Y%m%dT%H%M%S")
+    );
+    std::fs::write(
+        dir.join(format!("{}.json", id)),
+        serde_json::to_vec_pretty(snapshot)?,
+    )?;
+
+    let mut older = read_directory(dir)?
+        .into_iter()
+        .filter(|(_, other)| other.host == snapshot.host && other.list_id == snapshot.list_id)
+        .collect::<Vec<_>>();
+    older.reverse();
+    for (id, _) in older.into_iter().skip(MAX_SNAPSHOTS_PER_LIST) {
+        std::fs::remove_file(dir.join(format!("{}.json", id)))?;
+    }
+
+    Ok(())
+}
+
+/// All snapshots in the directory with their IDs, oldest first. Files that aren't snapshots are
+/// skipped.
+pub fn read_directory(dir: &Path) -> Result<Vec<(String, ListSnapshot)>, std::io::Error> {
+    let mut snapshots = Vec::new();
+
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json")
+            || path
+                .file_name()
+                .is_some_and(|name| name == FOLLOWS_COUNTS_FILE)
+        {
+            continue;
+        }
+        let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        match serde_json::from_slice::<ListSnapshot>(&std::fs::read(&path)?) {
+            Ok(snapshot) => snapshots.push((id.to_owned(), snapshot)),
+            Err(e) => tracing::warn!("skipping {}: {}", path.display(), e),
+        }
+    }
+
+    snapshots
+        .sort_by(|(a_id, a), (b_id, b)| (a.sync_started_at, a_id).cmp(&(b.sync_started_at, b_id)));
+    Ok(snapshots)
+}
+
+/// What restoring a snapshot changed.
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreStats {
+    pub list_id: String,
+    pub list_title: String,
+    pub added: usize,
+    pub removed: usize,
+    /// Accounts in the snapshot that the user no longer follows, and so can't be added back.
+    pub skipped: usize,
+}
+
+/// Put the list's membership back to the state of the snapshot. The current membership is
+/// snapshotted first, so a restore can be undone the same way.
+pub async fn restore(
+    client: &ApiClient,
+    api_cache: &mut ApiCache,
+    snapshot: &ListSnapshot,
+    snapshots: &SnapshotWriter,
+) -> Result<RestoreStats, SyncError> {
+    let follow_ids: BTreeSet<&str> = api_cache
+        .get_follows(client)
+        .await?
+        .iter()
+        .map(|account| account.id.as_str())
+        .collect();
+
+    let members = snapshot
+        .members
+        .iter()
+        .filter(|member| follow_ids.contains(member.id.as_str()))
+        .cloned()
+        .collect::<Vec<_>>();
+    let skipped = snapshot.members.len() - members.len();
+
+    let list = List {
+        id: snapshot.list_id.clone(),
+        title: snapshot.list_title.clone(),
+    };
+    let options = ReplaceOptions {
+        limits: &SafetyLimits::forced(),
+        incremental: false,
+    };
+    let change = replace_members(client, &list, members, snapshots, &options).await?;
+
+    Ok(RestoreStats {
+        list_id: list.id,
+        list_title: list.title,
+        added: change.added.len(),
+        removed: change.removed.len(),
+        skipped,
+    })
+}
+
+/// `snapshot list`: print the snapshots in the directory, oldest first.
+pub fn print_directory(dir: &Path) -> Result<(), Error> {
+    let snapshots = read_directory(dir)
+        .with_context(|| format!("failed to read snapshots from {}", dir.display()))?;
+
+    for (id, snapshot) in &snapshots {
+        println!(
+            "{}  {}  {:?}  {} members",
+            id,
+            snapshot.sync_started_at,
+            snapshot.list_title,
+            snapshot.members.len()
+        );
+    }
+    println!("{} snapshots", snapshots.len());
+
+    Ok(())
+}
+
+/// `snapshot restore`: restore one snapshot from the directory. The current membership is saved
+/// to the same directory first.
+pub async fn restore_from_directory(
+    hosts: &HostCoordinator,
+    dir: &Path,
+    id: &str,
+    token: &str,
+) -> Result<(), Error> {
+    let snapshot = read_directory(dir)
+        .with_context(|| format!("failed to read snapshots from {}", dir.display()))?
+        .into_iter()
+        .find(|(other_id, _)| other_id == id)
+        .map(|(_, snapshot)| snapshot)
+        .with_context(|| format!("no snapshot {} in {}", id, dir.display()))?;
+
+    let client = hosts.client(&snapshot.host, Some(token))?;
+    let writer = SnapshotTarget::Directory(dir.to_owned()).writer();
+    let stats = restore(&client, &mut ApiCache::default(), &snapshot, &writer).await?;
+
+    println!(
+        "restored {:?}: added {}, removed {}",
+        stats.list_title, stats.added, stats.removed
+    );
+    if stats.skipped > 0 {
+        println!(
+            "skipped {} accounts that you no longer follow",
+            stats.skipped
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn directory_pruning() {
+    let dir = std::env::temp_dir().join(format!("list-bot-snapshots-{}", std::process::id()));
+    let start = chrono::NaiveDate::from_ymd_opt(2024, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap();
+
+    for (list_id, count) in [("1", MAX_SNAPSHOTS_PER_LIST + 2), ("2", 1)] {
+        for day in 0..count {
+            let snapshot = ListSnapshot {
+                host: "example.com".to_owned(),
+                list_id: list_id.to_owned(),
+                list_title: "Friends #mutuals".to_owned(),
+                sync_started_at: start + chrono::Duration::days(day as i64),
+                members: vec![ListMember {
+                    id: day.to_string(),
+                    acct: "user".to_owned(),
+                }],
+            };
+            save_to_directory(&dir, &snapshot).unwrap();
+        }
+    }
+
+    let snapshots = read_directory(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(snapshots.len(), MAX_SNAPSHOTS_PER_LIST + 1);
+    let (id, oldest) = &snapshots[1];
+    assert_eq!(id, "example.com-1-20240103T000000");
+    assert_eq!(oldest.members[0].id, "2");
+}
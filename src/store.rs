@@ -2,24 +2,51 @@ use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use anyhow::Error;
-use chrono::{Duration, NaiveDateTime, Utc};
+use chrono::{Duration, NaiveDateTime, Timelike, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::migrate::MigrateDatabase;
 use sqlx::sqlite::SqlitePool;
 use sqlx::Sqlite;
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio::task::JoinHandle;
 
+use crate::api_cache::ApiCache;
 use crate::api_client::ApiClient;
 use crate::api_models::CredentialAccount;
-use crate::error::ResponseError;
+use crate::auth::OAUTH_SCOPES;
+use crate::crypto::TokenCipher;
+use crate::error::{ResponseError, SyncError};
+use crate::hosts::HostCoordinator;
+use crate::list_manager::ListSyncStats;
+use crate::metrics::METRICS;
+use crate::progress::{ProgressReporter, SyncProgress};
+use crate::runner::{self, RunOptions};
+use crate::safety::SafetyLimits;
+use crate::schedule::Schedule;
+use crate::snapshot::{self, ListRecord, ListSnapshot, SnapshotTarget, MAX_SNAPSHOTS_PER_LIST};
 
-type ImmediateSyncHandle = JoinHandle<Result<(), Error>>;
+struct ImmediateSync {
+    handle: JoinHandle<Result<(), SyncError>>,
+    progress: ProgressReporter,
+    /// Whether this reverts the last sync instead of syncing.
+    revert: bool,
+}
+
+/// After this many consecutive failures, the account is paused until the user resumes it.
+pub const MAX_FAILURES: i64 = 10;
+const MAX_RETRY_DELAY: Duration = Duration::days(1);
+const MIN_RETRY_DELAY: Duration = Duration::hours(1);
+/// When to try again after giving up waiting for other syncs on the same instance.
+const HOST_BUSY_RETRY_DELAY: Duration = Duration::minutes(5);
 
 #[derive(Clone)]
 pub struct Store {
     pool: SqlitePool,
-    immediate_syncs: Arc<Mutex<BTreeMap<AccountPk, (Account, ImmediateSyncHandle)>>>,
+    cipher: Arc<TokenCipher>,
+    immediate_syncs: Arc<Mutex<BTreeMap<AccountPk, ImmediateSync>>>,
+    limits: SafetyLimits,
+    incremental: bool,
+    hosts: HostCoordinator,
 }
 
 #[derive(Clone, Debug, Ord, Eq, PartialEq, PartialOrd, Deserialize, Serialize)]
@@ -43,7 +70,74 @@ pub struct Account {
     pub last_success_at: Option<NaiveDateTime>,
     pub failure_count: i64,
     pub last_error: Option<String>,
+    pub last_error_code: Option<String>,
+    pub list_count: i64,
+    pub needs_reauth: bool,
+    pub next_attempt_at: Option<NaiveDateTime>,
+    pub sync_frequency: String,
+    pub preferred_hour: Option<i64>,
+    pub follows_count: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncHistoryEntry {
+    pub started_at: NaiveDateTime,
+    pub finished_at: NaiveDateTime,
+    pub error_code: Option<String>,
+    pub error: Option<String>,
+    pub list_count: Option<i64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ListStats {
+    pub list_id: String,
+    pub title: String,
+    pub member_count: i64,
+    pub added: i64,
+    pub removed: i64,
+    pub synced_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+/// Everything we store about an account, except for secrets.
+#[derive(Debug, Serialize)]
+pub struct AccountExport {
+    pub host: String,
+    pub username: String,
+    pub created_at: NaiveDateTime,
+    pub last_success_at: Option<NaiveDateTime>,
+    pub failure_count: i64,
+    pub last_error: Option<String>,
+    pub last_error_code: Option<String>,
     pub list_count: i64,
+    pub needs_reauth: bool,
+    pub next_attempt_at: Option<NaiveDateTime>,
+    pub sync_frequency: String,
+    pub preferred_hour: Option<i64>,
+    pub follows_count: Option<i64>,
+    pub sync_history: Vec<SyncHistoryEntry>,
+    pub list_stats: Vec<ListStats>,
+    pub list_snapshots: Vec<ListSnapshot>,
+    pub list_records: Vec<ListRecord>,
+    pub api_tokens: Vec<ApiToken>,
+}
+
+/// An app registered on an instance via `/api/v1/apps`.
+#[derive(Debug, Clone)]
+pub struct OauthApp {
+    pub host: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub redirect_uri: String,
+    pub scopes: String,
+    pub created_at: NaiveDateTime,
 }
 
 pub struct RegisterAccount {
@@ -58,24 +152,126 @@ impl Account {
             username: self.username.clone(),
         }
     }
+
+    pub fn is_paused(&self) -> bool {
+        self.failure_count >= MAX_FAILURES
+    }
+
+    pub fn schedule(&self) -> Schedule {
+        let frequency = self.sync_frequency.parse();
+        let preferred_hour = self.preferred_hour.map(|hour| hour as u32);
+
+        match frequency.and_then(|frequency| Schedule::new(frequency, preferred_hour)) {
+            Ok(schedule) => schedule,
+            Err(e) => {
+                tracing::warn!(
+                    "invalid schedule for {}, using default: {}",
+                    self.primary_key().as_handle(),
+                    e
+                );
+                Schedule::default()
+            }
+        }
+    }
+}
+
+/// Exponential backoff: 1h, 2h, 4h, ... capped at one day.
+pub fn retry_delay(failure_count: i64) -> Duration {
+    let exponent = failure_count.clamp(1, 16) - 1;
+    (MIN_RETRY_DELAY * 2i32.pow(exponent as u32)).min(MAX_RETRY_DELAY)
 }
 
 impl Store {
-    pub async fn new(database_url: &str) -> Result<Self, Error> {
+    pub async fn new(
+        database_url: &str,
+        cipher: TokenCipher,
+        limits: SafetyLimits,
+        incremental: bool,
+        hosts: HostCoordinator,
+    ) -> Result<Self, Error> {
         let _ = Sqlite::create_database(database_url).await;
         let pool = SqlitePool::connect(database_url).await?;
 
         sqlx::migrate!("./migrations").run(&pool).await?;
         let immediate_syncs = Arc::new(Mutex::new(BTreeMap::new()));
 
-        Ok(Store {
+        let store = Store {
             pool,
+            cipher: Arc::new(cipher),
             immediate_syncs,
-        })
+            limits,
+            incremental,
+            hosts,
+        };
+
+        store.encrypt_tokens().await?;
+
+        Ok(store)
+    }
+
+    /// Encrypt plaintext tokens and app secrets, and re-encrypt those that were encrypted with an
+    /// old key.
+    async fn encrypt_tokens(&self) -> Result<(), Error> {
+        let accounts = sqlx::query!("select host, username, token from accounts")
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut count = 0;
+
+        for account in accounts {
+            if !self.cipher.needs_reencrypt(&account.token) {
+                continue;
+            }
+
+            let context = token_context(&account.host, &account.username);
+            let token = self.cipher.decrypt(&account.token, &context)?;
+            let token = self.cipher.encrypt(&token, &context)?;
+
+            sqlx::query!(
+                "update accounts set token = ?1 where host = ?2 and username = ?3",
+                token,
+                account.host,
+                account.username,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            count += 1;
+        }
+
+        let apps = sqlx::query!("select host, client_secret from oauth_apps")
+            .fetch_all(&self.pool)
+            .await?;
+
+        for app in apps {
+            if !self.cipher.needs_reencrypt(&app.client_secret) {
+                continue;
+            }
+
+            let context = oauth_app_context(&app.host);
+            let secret = self.cipher.decrypt(&app.client_secret, &context)?;
+            let secret = self.cipher.encrypt(&secret, &context)?;
+
+            sqlx::query!(
+                "update oauth_apps set client_secret = ?1 where host = ?2",
+                secret,
+                app.host,
+            )
+            .execute(&self.pool)
+            .await?;
+
+            count += 1;
+        }
+
+        if count > 0 {
+            tracing::info!("encrypted {} tokens and secrets with current key", count);
+        }
+
+        Ok(())
     }
 
     pub async fn register(&self, account: RegisterAccount) -> Result<Account, ResponseError> {
-        let client = ApiClient::new(&account.host, Some(&account.token))?;
+        let client = self.hosts.client(&account.host, Some(&account.token))?;
 
         let res: CredentialAccount = client
             .get(
@@ -87,22 +283,32 @@ impl Store {
             .json()
             .await?;
 
+        let token = self
+            .cipher
+            .encrypt(&account.token, &token_context(&account.host, &res.username))?;
+
         let account = Account {
             host: account.host,
-            token: account.token,
+            token,
             username: res.username,
             created_at: Utc::now().naive_utc(),
             last_success_at: None,
             last_error: None,
+            last_error_code: None,
             failure_count: 0,
             list_count: 0,
+            needs_reauth: false,
+            next_attempt_at: None,
+            sync_frequency: Schedule::default().frequency.to_string(),
+            preferred_hour: None,
+            follows_count: None,
         };
 
         // XXX: ugly
         sqlx::query!(
             "insert into accounts ( host, token, username, created_at, last_success_at, last_error, failure_count, list_count ) values ( ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8 )
             on conflict do update
-            set token = ?2",
+            set token = ?2, needs_reauth = false, failure_count = 0, next_attempt_at = null",
             account.host, account.token, account.username, account.created_at, account.last_success_at, account.last_error, account.failure_count, account.list_count,
         ).execute(&self.pool).await?;
 
@@ -122,9 +328,12 @@ impl Store {
         Ok(account)
     }
 
+    /// Start syncing the account right away. With `force`, the safety limits are ignored, for
+    /// when the user has confirmed that a large change is intended.
     pub async fn sync_immediate(
         &self,
         account_pk: AccountPk,
+        force: bool,
     ) -> Result<SyncImmediateResult, ResponseError> {
         let account = sqlx::query_as!(
             Account,
@@ -135,8 +344,21 @@ impl Store {
         .fetch_one(&self.pool)
         .await?;
 
+        if account.needs_reauth {
+            return Ok(SyncImmediateResult::NeedsReauth);
+        }
+
         let mut immediate_syncs = self.immediate_syncs.lock().await;
 
+        if immediate_syncs
+            .get(&account_pk)
+            .is_some_and(|immediate_sync| immediate_sync.revert)
+        {
+            return Err(ResponseError::BadRequest(
+                "the last sync is being reverted, try again when it is done".to_owned(),
+            ));
+        }
+
         if !immediate_syncs.contains_key(&account_pk) {
             if let Some(last_success_at) = account.last_success_at {
                 if last_success_at > Utc::now().naive_utc() - Duration::minutes(30) {
@@ -145,62 +367,436 @@ impl Store {
             }
         }
 
-        let handle = &mut immediate_syncs
+        if !immediate_syncs.contains_key(&account_pk) {
+            // the user is watching, so give them a fresh start
+            self.reset_backoff(&account_pk).await?;
+        }
+
+        immediate_syncs
             .entry(account_pk.clone())
             .or_insert_with(move || {
                 tracing::info!("immediate sync for {}", account.primary_key().as_handle());
                 let slf = self.clone();
-                let account2 = account.clone();
-                let future = async move { slf.run_once_and_log(account).await? };
-                (account2, tokio::spawn(future))
-            })
-            .1;
+                let progress = ProgressReporter::new();
+                let progress2 = progress.clone();
+                let future = async move {
+                    let result = slf
+                        .run_once_and_log(account, progress2.clone(), force)
+                        .await;
+                    progress2.report(SyncProgress::Finished);
+                    result?
+                };
+                ImmediateSync {
+                    handle: tokio::spawn(future),
+                    progress,
+                    revert: false,
+                }
+            });
+
+        Ok(Self::poll_immediate_sync(&mut immediate_syncs, &account_pk)
+            .await?
+            .expect("immediate sync was just inserted"))
+    }
+
+    /// The state of the immediate sync for this account, without starting a new one. Returns
+    /// `None` if there is no immediate sync. A finished result is only returned once.
+    pub async fn immediate_sync_status(
+        &self,
+        account_pk: &AccountPk,
+    ) -> Result<Option<SyncImmediateResult>, ResponseError> {
+        let mut immediate_syncs = self.immediate_syncs.lock().await;
+        Self::poll_immediate_sync(&mut immediate_syncs, account_pk).await
+    }
+
+    /// Watch the progress of the running immediate sync for this account, if there is one.
+    pub async fn subscribe_progress(
+        &self,
+        account_pk: &AccountPk,
+    ) -> Option<watch::Receiver<SyncProgress>> {
+        let immediate_syncs = self.immediate_syncs.lock().await;
+        immediate_syncs.get(account_pk)?.progress.subscribe()
+    }
+
+    async fn poll_immediate_sync(
+        immediate_syncs: &mut BTreeMap<AccountPk, ImmediateSync>,
+        account_pk: &AccountPk,
+    ) -> Result<Option<SyncImmediateResult>, ResponseError> {
+        let Some(ImmediateSync { handle, .. }) = immediate_syncs.get_mut(account_pk) else {
+            return Ok(None);
+        };
 
         if !handle.is_finished() {
-            return Ok(SyncImmediateResult::Pending);
+            return Ok(Some(SyncImmediateResult::Pending));
         }
 
         let result = handle.await?;
-        immediate_syncs.remove(&account_pk);
+        immediate_syncs.remove(account_pk);
 
-        Ok(match result {
+        Ok(Some(match result {
             Ok(()) => SyncImmediateResult::Ok,
             Err(e) => SyncImmediateResult::Error {
+                code: e.code(),
                 value: e.to_string(),
             },
-        })
+        }))
+    }
+
+    /// Clear the failure count so that the scheduler picks the account up again.
+    pub async fn reset_backoff(&self, pk: &AccountPk) -> Result<(), ResponseError> {
+        sqlx::query!(
+            "update accounts set
+            failure_count = 0,
+            next_attempt_at = null
+            where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_schedule(
+        &self,
+        pk: &AccountPk,
+        schedule: Schedule,
+    ) -> Result<(), ResponseError> {
+        let account = self.get_account(pk.clone()).await?;
+
+        let frequency = schedule.frequency.to_string();
+        let preferred_hour = schedule.preferred_hour.map(i64::from);
+
+        // reschedule right away, unless the account is backing off or was never synced
+        let next_attempt_at = match account.last_success_at {
+            Some(last_success_at) if account.failure_count == 0 => {
+                Some(schedule.next_after(last_success_at))
+            }
+            _ => account.next_attempt_at,
+        };
+
+        sqlx::query!(
+            "update accounts set
+            sync_frequency = ?1,
+            preferred_hour = ?2,
+            next_attempt_at = ?3
+            where host = ?4 and username = ?5",
+            frequency,
+            preferred_hour,
+            next_attempt_at,
+            pk.host,
+            pk.username,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub fn decrypt_token(&self, account: &Account) -> Result<String, SyncError> {
+        Ok(self.cipher.decrypt(
+            &account.token,
+            &token_context(&account.host, &account.username),
+        )?)
     }
 
-    async fn run_once_and_log(&self, account: Account) -> Result<Result<(), Error>, ResponseError> {
-        match crate::runner::run_once(&account.host, &account.token).await {
+    pub fn hosts(&self) -> &HostCoordinator {
+        &self.hosts
+    }
+
+    /// An API client for the account, sharing its connection pool and rate limit with the other
+    /// accounts on the same instance.
+    pub fn api_client(&self, account: &Account) -> Result<ApiClient, ResponseError> {
+        let token = self.decrypt_token(account)?;
+        self.hosts.client(&account.host, Some(&token))
+    }
+
+    pub async fn get_history(
+        &self,
+        pk: &AccountPk,
+        limit: i64,
+    ) -> Result<Vec<SyncHistoryEntry>, ResponseError> {
+        let history = sqlx::query_as!(
+            SyncHistoryEntry,
+            "select started_at, finished_at, error_code, error, list_count from sync_history
+            where host = ?1 and username = ?2
+            order by started_at desc
+            limit ?3",
+            pk.host,
+            pk.username,
+            limit,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(history)
+    }
+
+    /// Create a personal API token. The plaintext token is only returned here, we only store a
+    /// hash.
+    pub async fn create_api_token(
+        &self,
+        pk: &AccountPk,
+        name: &str,
+    ) -> Result<String, ResponseError> {
+        let token = crate::crypto::random_token("mlb_")?;
+        let token_hash = crate::crypto::hash_token(&token);
+        let now = Utc::now().naive_utc();
+
+        sqlx::query!(
+            "insert into api_tokens (host, username, name, token_hash, created_at)
+            values (?1, ?2, ?3, ?4, ?5)",
+            pk.host,
+            pk.username,
+            name,
+            token_hash,
+            now,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(token)
+    }
+
+    pub async fn get_api_tokens(&self, pk: &AccountPk) -> Result<Vec<ApiToken>, ResponseError> {
+        let tokens = sqlx::query_as!(
+            ApiToken,
+            "select id, name, created_at, last_used_at from api_tokens
+            where host = ?1 and username = ?2
+            order by created_at",
+            pk.host,
+            pk.username,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(tokens)
+    }
+
+    pub async fn delete_api_token(&self, pk: &AccountPk, id: i64) -> Result<(), ResponseError> {
+        sqlx::query!(
+            "delete from api_tokens where host = ?1 and username = ?2 and id = ?3",
+            pk.host,
+            pk.username,
+            id,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn get_account_by_api_token(
+        &self,
+        token: &str,
+    ) -> Result<Option<AccountPk>, ResponseError> {
+        let token_hash = crate::crypto::hash_token(token);
+
+        let pk = sqlx::query_as!(
+            AccountPk,
+            "update api_tokens set last_used_at = datetime('now')
+            where token_hash = ?1
+            returning host, username",
+            token_hash,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(pk)
+    }
+
+    pub async fn get_list_stats(&self, pk: &AccountPk) -> Result<Vec<ListStats>, ResponseError> {
+        let stats = sqlx::query_as!(
+            ListStats,
+            "select list_id, title, member_count, added, removed, synced_at from list_stats
+            where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(stats)
+    }
+
+    async fn save_list_stats(
+        &self,
+        account: &Account,
+        lists: &[ListSyncStats],
+        synced_at: NaiveDateTime,
+    ) -> Result<(), ResponseError> {
+        let mut tx = self.pool.begin().await?;
+
+        // lists that are no longer managed should not show stale numbers
+        sqlx::query!(
+            "delete from list_stats where host = ?1 and username = ?2",
+            account.host,
+            account.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        for list in lists {
+            let member_count = list.member_count as i64;
+            let added = list.added as i64;
+            let removed = list.removed as i64;
+            sqlx::query!(
+                "insert into list_stats (host, username, list_id, title, member_count, added, removed, synced_at)
+                select ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8
+                where exists (select 1 from accounts where host = ?1 and username = ?2)",
+                account.host,
+                account.username,
+                list.list_id,
+                list.title,
+                member_count,
+                added,
+                removed,
+                synced_at,
+            )
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn run_once_and_log(
+        &self,
+        account: Account,
+        progress: ProgressReporter,
+        force: bool,
+    ) -> Result<Result<(), SyncError>, ResponseError> {
+        let started_at = Utc::now().naive_utc();
+        let result = match self.decrypt_token(&account) {
+            Ok(token) => {
+                let options = RunOptions {
+                    progress,
+                    snapshots: SnapshotTarget::Database(self.clone(), account.primary_key()),
+                    limits: SafetyLimits {
+                        force,
+                        ..self.limits.clone()
+                    },
+                    incremental: self.incremental,
+                    hosts: self.hosts.clone(),
+                    ..RunOptions::default()
+                };
+                crate::runner::run_once(&account.host, &token, &options).await
+            }
+            Err(e) => Err(e),
+        };
+
+        let finished_at = Utc::now().naive_utc();
+        let (error_code, error, list_count) = match &result {
+            Ok(stats) => (None, None, Some(stats.list_count as i64)),
+            Err(e) => (Some(e.code()), Some(e.to_string()), None),
+        };
+
+        METRICS
+            .syncs
+            .with_label_values(&[error_code.unwrap_or("ok")])
+            .inc();
+        METRICS
+            .sync_duration
+            .observe((finished_at - started_at).num_milliseconds() as f64 / 1000.0);
+        if let Ok(stats) = &result {
+            for list in &stats.lists {
+                METRICS.list_members_added.inc_by(list.added as u64);
+                METRICS.list_members_removed.inc_by(list.removed as u64);
+            }
+        }
+
+        // we were too busy to sync, which is not the account's fault
+        if let Err(SyncError::HostBusy(e)) = &result {
+            tracing::info!(
+                "{}: {}, trying again later",
+                account.primary_key().as_handle(),
+                e
+            );
+            let next_attempt = format!("+{} seconds", HOST_BUSY_RETRY_DELAY.num_seconds());
+            sqlx::query!(
+                "update accounts set next_attempt_at = datetime('now', ?3)
+                where host = ?1 and username = ?2",
+                account.host,
+                account.username,
+                next_attempt,
+            )
+            .execute(&self.pool)
+            .await?;
+            return Ok(result.map(|_| ()));
+        }
+        // a sync started by the scheduler may finish after the account was deleted. writes for
+        // the account are skipped then, so that nothing is left behind.
+        sqlx::query!(
+            "insert into sync_history (host, username, started_at, finished_at, error_code, error, list_count)
+            select ?1, ?2, ?3, ?4, ?5, ?6, ?7
+            where exists (select 1 from accounts where host = ?1 and username = ?2)",
+            account.host,
+            account.username,
+            started_at,
+            finished_at,
+            error_code,
+            error,
+            list_count,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        match result {
             Ok(stats) => {
                 let list_count = stats.list_count as i64;
+                let now = Utc::now().naive_utc().with_nanosecond(0).unwrap();
+                self.save_list_stats(&account, &stats.lists, now).await?;
+                let next_attempt = account.schedule().next_after(now);
                 sqlx::query!(
                     "update accounts set
-                    last_success_at = datetime('now'),
+                    last_success_at = ?5,
+                    next_attempt_at = ?4,
                     list_count = ?1,
                     failure_count = 0,
-                    last_error = null
+                    last_error = null,
+                    last_error_code = null
                     where host = ?2 and username = ?3
                     ",
                     list_count,
                     account.host,
                     account.username,
+                    next_attempt,
+                    now,
                 )
                 .execute(&self.pool)
                 .await?;
                 Ok(Ok(()))
             }
             Err(e) => {
-                let e_str = format!("{:?}", e);
+                let e_str = e.to_string();
+                let e_code = e.code();
+                let next_attempt = format!(
+                    "+{} seconds",
+                    retry_delay(account.failure_count + 1).num_seconds()
+                );
+                let needs_reauth = matches!(e, SyncError::Auth(_));
+                if needs_reauth {
+                    tracing::warn!(
+                        "token for {} was rejected, waiting for re-login",
+                        account.primary_key().as_handle()
+                    );
+                }
+
                 sqlx::query!(
                     "update accounts set
                     failure_count = failure_count + 1,
-                    last_error = ?3
+                    last_error = ?3,
+                    last_error_code = ?4,
+                    needs_reauth = ?5,
+                    next_attempt_at = datetime('now', ?6)
                     where host = ?1 and username = ?2",
                     account.host,
                     account.username,
                     e_str,
+                    e_code,
+                    needs_reauth,
+                    next_attempt,
                 )
                 .execute(&self.pool)
                 .await?;
@@ -209,14 +805,543 @@ impl Store {
         }
     }
 
+    pub async fn save_snapshot(
+        &self,
+        pk: &AccountPk,
+        snapshot: &ListSnapshot,
+    ) -> Result<(), ResponseError> {
+        let members = serde_json::to_string(&snapshot.members)?;
+        let keep = MAX_SNAPSHOTS_PER_LIST as i64;
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "insert into list_snapshots (host, username, list_id, list_title, sync_started_at, members)
+            select ?1, ?2, ?3, ?4, ?5, ?6
+            where exists (select 1 from accounts where host = ?1 and username = ?2)",
+            pk.host,
+            pk.username,
+            snapshot.list_id,
+            snapshot.list_title,
+            snapshot.sync_started_at,
+            members,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "delete from list_snapshots
+            where host = ?1 and username = ?2 and list_id = ?3 and id not in (
+                select id from list_snapshots
+                where host = ?1 and username = ?2 and list_id = ?3
+                order by id desc
+                limit ?4
+            )",
+            pk.host,
+            pk.username,
+            snapshot.list_id,
+            keep,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn get_follows_count(&self, pk: &AccountPk) -> Result<Option<usize>, ResponseError> {
+        let row = sqlx::query!(
+            "select follows_count from accounts where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(row.follows_count.map(|count| count as usize))
+    }
+
+    pub async fn set_follows_count(
+        &self,
+        pk: &AccountPk,
+        count: usize,
+    ) -> Result<(), ResponseError> {
+        let count = count as i64;
+        sqlx::query!(
+            "update accounts set follows_count = ?3 where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+            count,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Snapshots of the account, oldest first. With `last_sync_only`, only those taken by the most
+    /// recent sync that changed anything.
+    pub async fn get_snapshots(
+        &self,
+        pk: &AccountPk,
+        last_sync_only: bool,
+    ) -> Result<Vec<ListSnapshot>, ResponseError> {
+        let rows = sqlx::query!(
+            r#"select list_id, list_title, sync_started_at as "sync_started_at: NaiveDateTime", members
+            from list_snapshots
+            where host = ?1 and username = ?2 and (not ?3 or sync_started_at = (
+                select max(sync_started_at) from list_snapshots where host = ?1 and username = ?2
+            ))
+            order by id"#,
+            pk.host,
+            pk.username,
+            last_sync_only,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(ListSnapshot {
+                    host: pk.host.clone(),
+                    list_id: row.list_id,
+                    list_title: row.list_title,
+                    sync_started_at: row.sync_started_at,
+                    members: serde_json::from_str(&row.members)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn get_list_record(
+        &self,
+        pk: &AccountPk,
+        list_id: &str,
+    ) -> Result<Option<ListRecord>, ResponseError> {
+        let row = sqlx::query!(
+            r#"select list_id, list_title, verified_at as "verified_at: NaiveDateTime", members
+            from list_records
+            where host = ?1 and username = ?2 and list_id = ?3"#,
+            pk.host,
+            pk.username,
+            list_id,
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+
+        Ok(Some(ListRecord {
+            list_id: row.list_id,
+            list_title: row.list_title,
+            verified_at: row.verified_at,
+            members: serde_json::from_str(&row.members)?,
+        }))
+    }
+
+    async fn get_list_records(&self, pk: &AccountPk) -> Result<Vec<ListRecord>, ResponseError> {
+        let rows = sqlx::query!(
+            r#"select list_id, list_title, verified_at as "verified_at: NaiveDateTime", members
+            from list_records
+            where host = ?1 and username = ?2"#,
+            pk.host,
+            pk.username,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(ListRecord {
+                    list_id: row.list_id,
+                    list_title: row.list_title,
+                    verified_at: row.verified_at,
+                    members: serde_json::from_str(&row.members)?,
+                })
+            })
+            .collect()
+    }
+
+    pub async fn save_list_record(
+        &self,
+        pk: &AccountPk,
+        record: &ListRecord,
+    ) -> Result<(), ResponseError> {
+        let members = serde_json::to_string(&record.members)?;
+        sqlx::query!(
+            "insert into list_records (host, username, list_id, list_title, verified_at, members)
+            select ?1, ?2, ?3, ?4, ?5, ?6
+            where exists (select 1 from accounts where host = ?1 and username = ?2)
+            on conflict do update
+            set list_title = ?4, verified_at = ?5, members = ?6",
+            pk.host,
+            pk.username,
+            record.list_id,
+            record.list_title,
+            record.verified_at,
+            members,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Start restoring all lists changed by the last sync to their previous members. Runs like
+    /// an immediate sync: in the background, with progress, and never alongside another sync of
+    /// the same instance. Calling it again while the revert runs returns its state. The revert
+    /// is snapshotted like a sync, so reverting again undoes it.
+    pub async fn revert_last_sync(
+        &self,
+        pk: &AccountPk,
+    ) -> Result<SyncImmediateResult, ResponseError> {
+        let mut immediate_syncs = self.immediate_syncs.lock().await;
+
+        match immediate_syncs.get(pk) {
+            Some(ImmediateSync { revert: true, .. }) => {}
+            Some(_) => {
+                return Err(ResponseError::BadRequest(
+                    "a sync is running, try again when it is done".to_owned(),
+                ))
+            }
+            None => {
+                let account = self.get_account(pk.clone()).await?;
+                let token = self.decrypt_token(&account)?;
+                let host_state = self.hosts.host(&account.host)?;
+                let snapshots = self.get_snapshots(pk, true).await?;
+                let writer = SnapshotTarget::Database(self.clone(), pk.clone()).writer();
+                let progress = ProgressReporter::new();
+                let progress2 = progress.clone();
+                let handle = pk.as_handle();
+
+                let future = async move {
+                    let result = async {
+                        let _slot = runner::wait_for_sync_slot(&host_state, &progress2).await?;
+                        let client = ApiClient::with_host(host_state.clone(), Some(&token))
+                            .with_progress(progress2.clone());
+                        let mut api_cache = ApiCache::default();
+
+                        for (index, snapshot) in snapshots.iter().enumerate() {
+                            progress2.report(SyncProgress::SyncingList {
+                                index: index + 1,
+                                total: snapshots.len(),
+                                title: snapshot.list_title.clone(),
+                            });
+                            let stats =
+                                snapshot::restore(&client, &mut api_cache, snapshot, &writer)
+                                    .await?;
+                            progress2.report(SyncProgress::ListDone {
+                                title: stats.list_title,
+                                added: stats.added,
+                                removed: stats.removed,
+                            });
+                        }
+                        Ok(())
+                    }
+                    .await;
+
+                    match &result {
+                        Ok(()) => tracing::info!(
+                            "reverted last sync of {} lists for {}",
+                            snapshots.len(),
+                            handle
+                        ),
+                        Err(e) => {
+                            tracing::warn!("failed to revert last sync for {}: {}", handle, e)
+                        }
+                    }
+                    progress2.report(SyncProgress::Finished);
+                    result
+                };
+
+                tracing::info!("reverting last sync for {}", pk.as_handle());
+                immediate_syncs.insert(
+                    pk.clone(),
+                    ImmediateSync {
+                        handle: tokio::spawn(future),
+                        progress,
+                        revert: true,
+                    },
+                );
+            }
+        }
+
+        Ok(Self::poll_immediate_sync(&mut immediate_syncs, pk)
+            .await?
+            .expect("revert was just checked or inserted"))
+    }
+
+    pub async fn export_account(&self, pk: &AccountPk) -> Result<AccountExport, ResponseError> {
+        let account = self.get_account(pk.clone()).await?;
+
+        Ok(AccountExport {
+            sync_history: self.get_history(pk, i64::MAX).await?,
+            list_stats: self.get_list_stats(pk).await?,
+            list_snapshots: self.get_snapshots(pk, false).await?,
+            list_records: self.get_list_records(pk).await?,
+            api_tokens: self.get_api_tokens(pk).await?,
+            host: account.host,
+            username: account.username,
+            created_at: account.created_at,
+            last_success_at: account.last_success_at,
+            failure_count: account.failure_count,
+            last_error: account.last_error,
+            last_error_code: account.last_error_code,
+            list_count: account.list_count,
+            needs_reauth: account.needs_reauth,
+            next_attempt_at: account.next_attempt_at,
+            sync_frequency: account.sync_frequency,
+            preferred_hour: account.preferred_hour,
+            follows_count: account.follows_count,
+        })
+    }
+
+    /// Revoke the account's access token and delete everything we store about it. Revocation is
+    /// best-effort, returns whether it succeeded.
+    pub async fn delete_account(&self, pk: &AccountPk) -> Result<bool, ResponseError> {
+        let account = self.get_account(pk.clone()).await?;
+
+        if let Some(immediate_sync) = self.immediate_syncs.lock().await.remove(pk) {
+            immediate_sync.handle.abort();
+        }
+
+        let revoked = match self.revoke_token(&account).await {
+            Ok(()) => true,
+            Err(e) => {
+                tracing::warn!("failed to revoke token for {}: {}", pk.as_handle(), e);
+                false
+            }
+        };
+
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query!(
+            "delete from sync_history where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "delete from list_stats where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "delete from list_records where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "delete from list_snapshots where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "delete from api_tokens where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query!(
+            "delete from accounts where host = ?1 and username = ?2",
+            pk.host,
+            pk.username,
+        )
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+
+        tracing::info!("deleted account {}", pk.as_handle());
+        Ok(revoked)
+    }
+
+    async fn revoke_token(&self, account: &Account) -> Result<(), ResponseError> {
+        let token = self.decrypt_token(account)?;
+        let client = self.hosts.client(&account.host, None)?;
+
+        // tokens created before apps were stored can't be revoked, since mastodon requires the
+        // client credentials
+        let app = self.get_oauth_app(&account.host).await?.ok_or_else(|| {
+            ResponseError::BadRequest("no oauth app stored for this instance".to_owned())
+        })?;
+
+        client
+            .post(
+                "/oauth/revoke",
+                Box::new(move |builder| {
+                    builder.form(&[
+                        ("client_id", app.client_id.as_str()),
+                        ("client_secret", app.client_secret.as_str()),
+                        ("token", token.as_str()),
+                    ])
+                }),
+            )
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+
+    /// Get the app we registered on this instance, or register a new one if there is none yet or
+    /// its redirect URI or scopes are outdated.
+    pub async fn get_or_register_oauth_app(
+        &self,
+        host: &str,
+        service_uri: &str,
+        redirect_uri: &str,
+    ) -> Result<OauthApp, ResponseError> {
+        if let Some(app) = self.get_oauth_app(host).await? {
+            if app.redirect_uri == redirect_uri && app.scopes == OAUTH_SCOPES {
+                return Ok(app);
+            }
+        }
+
+        #[derive(Deserialize)]
+        struct OauthAppResponse {
+            client_id: String,
+            client_secret: String,
+        }
+
+        let client = self.hosts.client(host, None)?;
+        let res: OauthAppResponse = client
+            .client
+            .post(client.url("/api/v1/apps"))
+            .form(&[
+                ("client_name", "Mastodon List Bot"),
+                ("website", service_uri),
+                ("scopes", OAUTH_SCOPES),
+                ("redirect_uris", redirect_uri),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let app = OauthApp {
+            host: host.to_owned(),
+            client_id: res.client_id,
+            client_secret: res.client_secret,
+            redirect_uri: redirect_uri.to_owned(),
+            scopes: OAUTH_SCOPES.to_owned(),
+            created_at: Utc::now().naive_utc(),
+        };
+
+        let client_secret = self
+            .cipher
+            .encrypt(&app.client_secret, &oauth_app_context(host))?;
+
+        sqlx::query!(
+            "insert into oauth_apps (host, client_id, client_secret, redirect_uri, scopes, created_at)
+            values (?1, ?2, ?3, ?4, ?5, ?6)
+            on conflict do update
+            set client_id = ?2, client_secret = ?3, redirect_uri = ?4, scopes = ?5, created_at = ?6",
+            app.host,
+            app.client_id,
+            client_secret,
+            app.redirect_uri,
+            app.scopes,
+            app.created_at,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        tracing::info!("registered new oauth app on {}", host);
+        Ok(app)
+    }
+
+    pub async fn get_oauth_app(&self, host: &str) -> Result<Option<OauthApp>, ResponseError> {
+        let Some(mut app) = sqlx::query_as!(
+            OauthApp,
+            "select host, client_id, client_secret, redirect_uri, scopes, created_at
+            from oauth_apps where host = ?1",
+            host
+        )
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+
+        app.client_secret = self
+            .cipher
+            .decrypt(&app.client_secret, &oauth_app_context(host))?;
+        Ok(Some(app))
+    }
+
+    /// Forget the app for this instance, e.g. because the instance no longer accepts it.
+    pub async fn delete_oauth_app(&self, host: &str) -> Result<(), ResponseError> {
+        sqlx::query!("delete from oauth_apps where host = ?1", host)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Check that the database is reachable.
+    pub async fn ping(&self) -> Result<(), ResponseError> {
+        sqlx::query!("select 1 as one")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Update the account gauges in `METRICS` from the database.
+    pub async fn update_account_metrics(&self) -> Result<(), ResponseError> {
+        let counts = sqlx::query!(
+            r#"select
+            count(*) filter (where needs_reauth) as "needs_reauth!: i64",
+            count(*) filter (where not needs_reauth and failure_count >= ?1) as "paused!: i64",
+            count(*) filter (where not needs_reauth and failure_count > 0 and failure_count < ?1) as "failing!: i64",
+            count(*) filter (where not needs_reauth and failure_count = 0) as "active!: i64",
+            count(*) filter (
+                where not needs_reauth and failure_count < ?1
+                and (next_attempt_at is null or next_attempt_at <= datetime('now'))
+            ) as "due!: i64"
+            from accounts"#,
+            MAX_FAILURES
+        )
+        .fetch_one(&self.pool)
+        .await?;
+
+        for (state, count) in [
+            ("active", counts.active),
+            ("failing", counts.failing),
+            ("paused", counts.paused),
+            ("needs_reauth", counts.needs_reauth),
+        ] {
+            METRICS.accounts.with_label_values(&[state]).set(count);
+        }
+        METRICS.queue_depth.set(counts.due);
+
+        Ok(())
+    }
+
     pub async fn sync_all_accounts(&self) -> Result<(usize, usize), Error> {
-        // sync all accounts that have already been synced at least once
+        // sync all accounts that are due, oldest first
         let results = sqlx::query_as!(
             Account,
             "select * from accounts
-            where (last_success_at is null or last_success_at < datetime('now', '-1 days'))
-            and failure_count < 10
-            limit 10"
+            where (next_attempt_at is null or next_attempt_at <= datetime('now'))
+            and failure_count < ?1
+            and not needs_reauth
+            order by next_attempt_at
+            limit 10",
+            MAX_FAILURES
         )
         .fetch_all(&self.pool)
         .await?;
@@ -234,7 +1359,10 @@ impl Store {
                 continue;
             }
 
-            match self.run_once_and_log(account).await? {
+            match self
+                .run_once_and_log(account, ProgressReporter::default(), false)
+                .await?
+            {
                 Ok(_) => {
                     success_count += 1;
                 }
@@ -248,12 +1376,31 @@ impl Store {
     }
 }
 
+fn token_context(host: &str, username: &str) -> String {
+    format!("{}@{}", username, host)
+}
+
+/// Like `token_context`, for the client secret of the app registered on `host`.
+fn oauth_app_context(host: &str) -> String {
+    format!("oauth-app:{}", host)
+}
+
 #[derive(Serialize)]
 #[serde(tag = "type")]
 #[serde(rename_all = "snake_case")]
 pub enum SyncImmediateResult {
     Ok,
-    Error { value: String },
+    Error { code: &'static str, value: String },
     Pending,
     TooMany,
+    NeedsReauth,
+}
+
+#[test]
+fn test_retry_delay() {
+    assert_eq!(retry_delay(1), Duration::hours(1));
+    assert_eq!(retry_delay(2), Duration::hours(2));
+    assert_eq!(retry_delay(4), Duration::hours(8));
+    assert_eq!(retry_delay(6), Duration::days(1));
+    assert_eq!(retry_delay(100), Duration::days(1));
 }
@@ -0,0 +1,101 @@
+use crate::error::SyncError;
+use crate::hosts::HostCoordinator;
+use crate::list_manager::{FilterError, ListManagerTerms};
+use crate::runner::get_lists;
+
+/// Check a filter expression or list title offline, with the same parser the sync uses. Prints
+/// the normalized filter and the parsed terms, or where parsing failed. Anything the sync would
+/// ignore after the filter is reported as a warning. Returns whether the filter is valid.
+pub fn validate_expression(input: &str) -> bool {
+    let exact = ListManagerTerms::parse_exact(input);
+    match ListManagerTerms::from_title(input) {
+        Some(Ok(terms)) => {
+            println!("normalized: {}", terms);
+            println!("meaning: {}", terms.describe());
+            println!("terms: {:#?}", terms.0);
+            if let Err(e) = exact {
+                println!("warning: {}, the rest is ignored", e.message);
+                print_marker(input, &e);
+            }
+            true
+        }
+        lenient => {
+            match (exact, lenient) {
+                (Err(e), _) => {
+                    println!("error: {}", e.message);
+                    print_marker(input, &e);
+                }
+                (Ok(_), Some(Err(message))) => println!("error: {}", message),
+                (Ok(_), _) => println!("error: expected a # followed by the filter"),
+            }
+            false
+        }
+    }
+}
+
+fn print_marker(input: &str, e: &FilterError) {
+    println!("  {}", input);
+    println!("  {}^", " ".repeat(column(input, e)));
+}
+
+/// Terminal column of the error, for the `^` marker.
+fn column(input: &str, e: &FilterError) -> usize {
+    input
+        .get(..e.position)
+        .map_or(e.position, |before| before.chars().count())
+}
+
+/// Parse the titles of all lists on the account, and print which ones are managed, ignored or
+/// broken. Returns the number of broken lists.
+pub async fn validate_lists(
+    hosts: &HostCoordinator,
+    host: &str,
+    token: &str,
+) -> Result<usize, SyncError> {
+    let api_client = hosts.client(host, Some(token))?;
+    let lists = get_lists(&api_client).await?;
+
+    let mut managed = 0;
+    let mut ignored = 0;
+    let mut broken = 0;
+
+    for list in &lists {
+        match ListManagerTerms::from_title(&list.title) {
+            None => {
+                println!("ignored  {}  {:?}", list.id, list.title);
+                ignored += 1;
+            }
+            Some(Ok(terms)) => {
+                println!(
+                    "managed  {}  {:?}: {} ({})",
+                    list.id,
+                    list.title,
+                    terms,
+                    terms.describe()
+                );
+                if let Err(e) = ListManagerTerms::parse_exact(&list.title) {
+                    println!("         warning: {}, the rest of the title is ignored", e);
+                }
+                managed += 1;
+            }
+            Some(Err(lenient_error)) => {
+                let message = match ListManagerTerms::parse_exact(&list.title) {
+                    Err(e) => e.to_string(),
+                    Ok(_) => lenient_error,
+                };
+                println!("broken   {}  {:?}: {}", list.id, list.title, message);
+                broken += 1;
+            }
+        }
+    }
+
+    println!(
+        "{} lists: {} managed, {} ignored, {} broken",
+        lists.len(),
+        managed,
+        ignored,
+        broken
+    );
+
+    Ok(broken)
+}
@@ -0,0 +1,246 @@
+use axum::async_trait;
+use axum::extract::{FromRequestParts, State};
+use axum::http::{header::AUTHORIZATION, request::Parts, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::{Json, Router};
+use chrono::NaiveDateTime;
+use serde::Serialize;
+
+use crate::auth::LoggedIn;
+use crate::error::{describe_sync_error, ResponseError};
+use crate::list_manager::ListManagerTerms;
+use crate::store::{AccountPk, SyncHistoryEntry, SyncImmediateResult};
+use crate::AppState;
+
+const HISTORY_LIMIT: i64 = 50;
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/account", get(get_account))
+        .route("/lists", get(get_lists))
+        .route("/history", get(get_history))
+        .route("/sync", get(get_sync).post(post_sync))
+}
+
+/// Like `ResponseError`, but rendered as JSON.
+pub struct ApiError(ResponseError);
+
+impl<E: Into<ResponseError>> From<E> for ApiError {
+    fn from(e: E) -> Self {
+        ApiError(e.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, code) = match self.0 {
+            ResponseError::NeedsAuth => (StatusCode::UNAUTHORIZED, "needs_auth"),
+            ResponseError::BadRequest(_) => (StatusCode::BAD_REQUEST, "bad_request"),
+            ResponseError::InvalidCsrfToken => (StatusCode::FORBIDDEN, "invalid_csrf_token"),
+            ResponseError::Sync(ref e) => (StatusCode::BAD_GATEWAY, e.code()),
+            _ => {
+                tracing::error!("error while serving API request: {}", self.0);
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal")
+            }
+        };
+
+        #[derive(Serialize)]
+        struct ErrorBody {
+            error: &'static str,
+            message: String,
+        }
+
+        let body = ErrorBody {
+            error: code,
+            message: self.0.to_string(),
+        };
+
+        (status, Json(body)).into_response()
+    }
+}
+
+/// Authenticates API requests, either with a personal API token passed as `Authorization: Bearer
+/// ...`, or with the browser session.
+pub struct ApiLogin(pub AccountPk);
+
+#[async_trait]
+impl FromRequestParts<AppState> for ApiLogin {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(
+        req: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        if let Some(header) = req.headers.get(AUTHORIZATION) {
+            let token = header
+                .to_str()
+                .ok()
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .ok_or(ResponseError::NeedsAuth)?;
+
+            let account = state
+                .store
+                .get_account_by_api_token(token.trim())
+                .await?
+                .ok_or(ResponseError::NeedsAuth)?;
+
+            return Ok(ApiLogin(account));
+        }
+
+        let login = LoggedIn::from_request_parts(req, state)
+            .await
+            .map_err(|_| ResponseError::NeedsAuth)?;
+        Ok(ApiLogin(login.account()?))
+    }
+}
+
+#[derive(Serialize)]
+struct ApiSyncError {
+    code: String,
+    message: String,
+    summary: &'static str,
+    action: &'static str,
+}
+
+#[derive(Serialize)]
+struct ApiSchedule {
+    frequency: String,
+    preferred_hour: Option<u32>,
+    description: String,
+}
+
+#[derive(Serialize)]
+struct ApiAccount {
+    host: String,
+    username: String,
+    created_at: NaiveDateTime,
+    last_success_at: Option<NaiveDateTime>,
+    next_attempt_at: Option<NaiveDateTime>,
+    failure_count: i64,
+    paused: bool,
+    needs_reauth: bool,
+    list_count: i64,
+    last_error: Option<ApiSyncError>,
+    schedule: ApiSchedule,
+}
+
+async fn get_account(
+    State(state): State<AppState>,
+    ApiLogin(account_pk): ApiLogin,
+) -> Result<Json<ApiAccount>, ApiError> {
+    let account = state.store.get_account(account_pk).await?;
+    let schedule = account.schedule();
+
+    let last_error = account.last_error.clone().map(|message| {
+        let code = account
+            .last_error_code
+            .clone()
+            .unwrap_or_else(|| "internal".to_owned());
+        let (summary, action) = describe_sync_error(&code);
+        ApiSyncError {
+            code,
+            message,
+            summary,
+            action,
+        }
+    });
+
+    Ok(Json(ApiAccount {
+        paused: account.is_paused(),
+        schedule: ApiSchedule {
+            frequency: schedule.frequency.to_string(),
+            preferred_hour: schedule.preferred_hour,
+            description: schedule.describe(),
+        },
+        last_error,
+        host: account.host,
+        username: account.username,
+        created_at: account.created_at,
+        last_success_at: account.last_success_at,
+        next_attempt_at: account.next_attempt_at,
+        failure_count: account.failure_count,
+        needs_reauth: account.needs_reauth,
+        list_count: account.list_count,
+    }))
+}
+
+#[derive(Serialize)]
+struct ApiList {
+    id: String,
+    title: String,
+    managed: bool,
+    /// Normalized filter expression, if the list is managed.
+    filter: Option<String>,
+    terms: Option<ListManagerTerms>,
+    /// Set if the title contains a filter that could not be parsed.
+    error: Option<String>,
+}
+
+async fn get_lists(
+    State(state): State<AppState>,
+    ApiLogin(account_pk): ApiLogin,
+) -> Result<Json<Vec<ApiList>>, ApiError> {
+    let account = state.store.get_account(account_pk).await?;
+    let client = state.store.api_client(&account)?;
+    let lists = crate::runner::get_lists(&client).await?;
+
+    let result = lists
+        .into_iter()
+        .map(|list| {
+            let (terms, error) = match ListManagerTerms::from_title(&list.title) {
+                Some(Ok(terms)) => (Some(terms), None),
+                Some(Err(e)) => (None, Some(e)),
+                None => (None, None),
+            };
+
+            ApiList {
+                id: list.id,
+                title: list.title,
+                managed: terms.is_some(),
+                filter: terms.as_ref().map(ToString::to_string),
+                terms,
+                error,
+            }
+        })
+        .collect();
+
+    Ok(Json(result))
+}
+
+async fn get_history(
+    State(state): State<AppState>,
+    ApiLogin(account_pk): ApiLogin,
+) -> Result<Json<Vec<SyncHistoryEntry>>, ApiError> {
+    Ok(Json(
+        state.store.get_history(&account_pk, HISTORY_LIMIT).await?,
+    ))
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+enum ApiSyncStatus {
+    None,
+    #[serde(untagged)]
+    Some(SyncImmediateResult),
+}
+
+/// Status of the last sync triggered via `POST /sync`. A finished result is only reported once.
+async fn get_sync(
+    State(state): State<AppState>,
+    ApiLogin(account_pk): ApiLogin,
+) -> Result<Json<ApiSyncStatus>, ApiError> {
+    let status = state.store.immediate_sync_status(&account_pk).await?;
+    Ok(Json(match status {
+        Some(status) => ApiSyncStatus::Some(status),
+        None => ApiSyncStatus::None,
+    }))
+}
+
+async fn post_sync(
+    State(state): State<AppState>,
+    ApiLogin(account_pk): ApiLogin,
+) -> Result<Json<SyncImmediateResult>, ApiError> {
+    Ok(Json(state.store.sync_immediate(account_pk, false).await?))
+}